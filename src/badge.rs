@@ -0,0 +1,147 @@
+use clap::ValueEnum;
+use xmltv::Programme;
+
+use crate::export::Options;
+use crate::xmltv::parse_from_str;
+
+/// Placement of the programme badges in the feed items. See [`badges`].
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum BadgePlacement {
+    /// Prefix the item title, for example, "[NEW] Title"
+    Title,
+
+    /// Add a "Badges" row to the item description
+    Description,
+}
+
+/// Returns the badges of the programme: "NEW", "PREMIERE", "LAST CHANCE" and "Repeat of <date>".
+///
+/// The date of a repeat is the previously shown date, in the output timezone and date format.
+/// A repeat without a previously shown date, or with a date that fails to parse, has the badge "Repeat".
+pub(crate) fn badges(programme: &Programme, options: &Options) -> Vec<String> {
+    let mut badges = vec![];
+
+    if programme.new {
+        badges.push("NEW".to_string());
+    }
+    if programme.premiere.is_some() {
+        badges.push("PREMIERE".to_string());
+    }
+    if programme.last_chance.is_some() {
+        badges.push("LAST CHANCE".to_string());
+    }
+    if let Some(previously_shown) = &programme.previously_shown {
+        let date = previously_shown.start.as_ref().and_then(|start| {
+            parse_from_str(start, options.xmltv_datetime_format, options.input_timezone).ok()
+        });
+        badges.push(match date {
+            Some(date) => {
+                let date = options.to_output_timezone(date);
+                format!("Repeat of {}", date.format(options.date_format))
+            }
+            None => "Repeat".to_string(),
+        });
+    }
+
+    badges
+}
+
+/// Returns the title prefixed with the badges, for example, "[NEW] [PREMIERE] Title".
+pub(crate) fn title_with_badges(title: &str, badges: &[String]) -> String {
+    badges
+        .iter()
+        .map(|badge| format!("[{badge}] "))
+        .chain([title.to_string()])
+        .collect()
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use xmltv::{PreviouslyShown, ValueAndLang};
+
+    use super::*;
+    use crate::xmltv::reader::Reader;
+
+    #[test]
+    fn test() {
+        let options = Options {
+            date_format: "%Y-%m-%d",
+            ..Default::default()
+        };
+        let premiere = Some(ValueAndLang {
+            value: String::new(),
+            lang: None,
+        });
+        let previously_shown = |start: Option<&str>| {
+            Some(PreviouslyShown {
+                start: start.map(str::to_string),
+                channel: None,
+            })
+        };
+
+        let tests = [
+            (Programme::default(), vec![]),
+            (
+                Programme {
+                    new: true,
+                    premiere: premiere.clone(),
+                    ..Default::default()
+                },
+                vec!["NEW", "PREMIERE"],
+            ),
+            (
+                Programme {
+                    last_chance: premiere.clone(),
+                    previously_shown: previously_shown(Some("20240101")),
+                    ..Default::default()
+                },
+                vec!["LAST CHANCE", "Repeat of 2024-01-01"],
+            ),
+            (
+                Programme {
+                    previously_shown: previously_shown(None),
+                    ..Default::default()
+                },
+                vec!["Repeat"],
+            ),
+        ];
+        for (programme, expected) in tests {
+            assert_eq!(badges(&programme, &options), expected, "for {programme:?}");
+        }
+
+        let badges = ["NEW".to_string(), "PREMIERE".to_string()];
+        assert_eq!(
+            title_with_badges("Title", &badges),
+            "[NEW] [PREMIERE] Title"
+        );
+        assert_eq!(title_with_badges("Title", &[]), "Title");
+    }
+
+    #[test]
+    fn test_read_previously_shown() {
+        const INPUT: &str = r#"<tv>
+  <programme channel="a.tv" start="20240102100000 +0000">
+    <title>Show</title>
+    <previously-shown start="20240101200000 +0000" channel="b.tv"/>
+  </programme>
+</tv>"#;
+        let options = Options {
+            date_format: "%Y-%m-%d",
+            ..Default::default()
+        };
+
+        let listing = Reader::new(INPUT.as_bytes()).read_listing().unwrap();
+        let programme = &listing.programmes[0];
+        assert_eq!(
+            programme.previously_shown,
+            Some(PreviouslyShown {
+                start: Some("20240101200000 +0000".to_string()),
+                channel: Some("b.tv".to_string()),
+            })
+        );
+        assert_eq!(badges(programme, &options), ["Repeat of 2024-01-01"]);
+    }
+}
@@ -0,0 +1,286 @@
+use chrono::{DateTime, FixedOffset};
+use clap::ValueEnum;
+use std::collections::HashMap;
+use xmltv::{Programme, ValueAndLang};
+
+use crate::export::{Annotations, PendingProgramme};
+
+/// Episode number systems that identify an episode, in order of preference.
+const EPISODE_NUM_SYSTEMS: [&str; 3] = ["dd_progid", "xmltv_ns", "onscreen"];
+
+/// Airing of a deduplicated episode that supplies the item publication date and GUID.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum DedupAiring {
+    /// The first airing
+    #[default]
+    First,
+
+    /// The first airing that has not ended yet, or else the last airing
+    Next,
+
+    /// The last airing
+    Last,
+}
+
+/// Episode identity of a programme.
+#[derive(Debug, Eq, Hash, PartialEq)]
+enum EpisodeKey {
+    /// Title, episode number system and episode number
+    EpisodeNum(String, String, String),
+
+    /// Title, sub-title and description
+    Content(String, String, String),
+}
+
+impl EpisodeKey {
+    /// Returns the episode identity of the programme: the title with the "dd_progid", "xmltv_ns" or
+    /// "onscreen" episode number, or else the title with the sub-title and description.
+    ///
+    /// A programme without episode number, sub-title and description has no episode identity.
+    fn new(programme: &Programme) -> Option<Self> {
+        let title = normalize(&programme.titles);
+
+        let episode_num = EPISODE_NUM_SYSTEMS.iter().find_map(|system| {
+            programme
+                .episode_num
+                .iter()
+                .find(|episode_num| episode_num.system.eq_ignore_ascii_case(system))
+                .map(|episode_num| (system, episode_num.value.split_whitespace().collect()))
+        });
+        if let Some((system, value)) = episode_num {
+            return Some(EpisodeKey::EpisodeNum(title, system.to_string(), value));
+        }
+
+        let sub_title = normalize(&programme.sub_titles);
+        let description = normalize(&programme.descriptions);
+        if sub_title.is_empty() && description.is_empty() {
+            return None;
+        }
+
+        Some(EpisodeKey::Content(title, sub_title, description))
+    }
+}
+
+/// Returns the values, lowercased and with whitespace collapsed.
+fn normalize(values: &[ValueAndLang]) -> String {
+    values
+        .iter()
+        .flat_map(|value| value.value.split_whitespace())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Deduplicates repeated airings of the same episode, into one programme with all the airings.
+///
+/// See [`Options::dedup`](crate::export::Options::dedup).
+#[derive(Default)]
+pub(crate) struct Dedup<'a> {
+    /// Episodes, in order of their first added airing
+    episodes: Vec<Episode<'a>>,
+
+    /// Index into the episodes, by episode identity
+    keys: HashMap<EpisodeKey, usize>,
+}
+
+#[derive(Default)]
+struct Episode<'a> {
+    airings: Vec<PendingProgramme<'a>>,
+}
+
+impl<'a> Dedup<'a> {
+    /// Adds an airing of a programme.
+    pub(crate) fn add(&mut self, pending: PendingProgramme<'a>) {
+        let index = match EpisodeKey::new(&pending.programme) {
+            Some(key) => *self.keys.entry(key).or_insert_with(|| {
+                self.episodes.push(Episode::default());
+                self.episodes.len() - 1
+            }),
+            None => {
+                self.episodes.push(Episode::default());
+                self.episodes.len() - 1
+            }
+        };
+
+        self.episodes[index].airings.push(pending);
+    }
+
+    /// Returns one programme per episode, from the selected airing, annotated with all the airings
+    /// in start time order and with the matching watchlist rules of all the airings.
+    pub(crate) fn finish(
+        self,
+        selected: DedupAiring,
+        now: DateTime<FixedOffset>,
+    ) -> impl Iterator<Item = PendingProgramme<'a>> {
+        self.episodes.into_iter().map(move |mut episode| {
+            episode
+                .airings
+                .sort_by_key(|pending| (pending.airing.start, pending.airing.stop));
+
+            let last = episode.airings.len() - 1;
+            let index = match selected {
+                DedupAiring::First => 0,
+                DedupAiring::Next => episode
+                    .airings
+                    .iter()
+                    .position(|pending| pending.airing.stop > now)
+                    .unwrap_or(last),
+                DedupAiring::Last => last,
+            };
+
+            let mut rules = vec![];
+            for rule in episode.airings.iter().flat_map(|p| &p.annotations.rules) {
+                if !rules.contains(rule) {
+                    rules.push(*rule);
+                }
+            }
+            let airings = episode
+                .airings
+                .iter()
+                .map(|pending| pending.airing.clone())
+                .collect();
+
+            let mut pending = episode.airings.swap_remove(index);
+            pending.annotations = Annotations { rules, airings };
+            pending
+        })
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+    use pretty_assertions::assert_eq;
+    use xmltv::EpisodeNum;
+
+    use super::*;
+    use crate::error::ProgrammeContext;
+    use crate::export::Airing;
+
+    fn value(value: &str) -> Vec<ValueAndLang> {
+        vec![ValueAndLang {
+            value: value.to_string(),
+            lang: None,
+        }]
+    }
+
+    fn programme(channel: &str, start: &str, title: &str, episode_num: Option<&str>) -> Programme {
+        Programme {
+            channel: channel.to_string(),
+            start: start.to_string(),
+            titles: value(title),
+            episode_num: episode_num
+                .map(|episode_num| EpisodeNum {
+                    value: episode_num.to_string(),
+                    system: "xmltv_ns".to_string(),
+                })
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_episode_key() {
+        let with_description = |description: &str| Programme {
+            descriptions: value(description),
+            ..programme("a", "", "News", None)
+        };
+
+        assert_eq!(
+            EpisodeKey::new(&programme("a", "", "Show", Some("0 . 1 . 0/1"))),
+            EpisodeKey::new(&programme("b", "", "show", Some("0.1.0/1")))
+        );
+        assert_ne!(
+            EpisodeKey::new(&programme("a", "", "Show", Some("0.1.0/1"))),
+            EpisodeKey::new(&programme("a", "", "Other show", Some("0.1.0/1")))
+        );
+        assert_eq!(
+            EpisodeKey::new(&with_description("The  news.\n")),
+            EpisodeKey::new(&with_description("The news."))
+        );
+        assert_eq!(EpisodeKey::new(&programme("a", "", "News", None)), None);
+    }
+
+    #[test]
+    fn test() {
+        let datetime = |datetime| DateTime::parse_from_rfc3339(datetime).unwrap();
+        let airing = |channel: &str, start| Airing {
+            channel: channel.to_string(),
+            start: datetime(start),
+            stop: datetime(start) + TimeDelta::hours(1),
+        };
+        let now = datetime("2024-01-01T12:00:00+00:00");
+
+        // Channel, start time, title, episode number and watchlist rules
+        let airings: [(_, _, _, _, &[&str]); 5] = [
+            (
+                "a",
+                "2024-01-01T13:00:00+00:00",
+                "Show",
+                Some("0.1.0"),
+                &["x"],
+            ),
+            ("a", "2024-01-01T10:00:00+00:00", "News", None, &[]),
+            ("a", "2024-01-01T10:00:00+00:00", "Show", Some("0.1.0"), &[]),
+            (
+                "b",
+                "2024-01-02T10:00:00+00:00",
+                "Show",
+                Some("0.1.0"),
+                &["y", "x"],
+            ),
+            ("a", "2024-01-01T11:00:00+00:00", "News", None, &[]),
+        ];
+
+        let tests = [
+            (DedupAiring::First, "2024-01-01T10:00:00+00:00"),
+            (DedupAiring::Next, "2024-01-01T13:00:00+00:00"),
+            (DedupAiring::Last, "2024-01-02T10:00:00+00:00"),
+        ];
+        for (selected, expected) in tests {
+            let mut dedup = Dedup::default();
+            for (channel, start, title, episode_num, rules) in airings {
+                let programme = programme(channel, start, title, episode_num);
+                dedup.add(PendingProgramme {
+                    context: ProgrammeContext::new(&programme, 0, None),
+                    programme,
+                    airing: airing(channel, start),
+                    annotations: Annotations {
+                        rules: rules.to_vec(),
+                        ..Default::default()
+                    },
+                });
+            }
+
+            let episodes: Vec<_> = dedup.finish(selected, now).collect();
+            let starts: Vec<_> = episodes
+                .iter()
+                .map(|p| p.programme.start.as_str())
+                .collect();
+            assert_eq!(
+                starts,
+                [
+                    expected,
+                    "2024-01-01T10:00:00+00:00",
+                    "2024-01-01T11:00:00+00:00"
+                ],
+                "for {selected:?}"
+            );
+
+            let annotations = &episodes[0].annotations;
+            assert_eq!(annotations.rules, ["x", "y"]);
+            assert_eq!(
+                annotations.airings,
+                [
+                    airing("a", "2024-01-01T10:00:00+00:00"),
+                    airing("a", "2024-01-01T13:00:00+00:00"),
+                    airing("b", "2024-01-02T10:00:00+00:00"),
+                ]
+            );
+            assert_eq!(episodes[1].annotations.airings.len(), 1);
+        }
+    }
+}
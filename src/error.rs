@@ -1,4 +1,8 @@
+use std::fmt;
 use thiserror::Error;
+use xmltv::Programme;
+
+use crate::xmltv::reader::Position;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -7,18 +11,132 @@ pub enum Error {
     #[error(transparent)]
     Atom(#[from] atom_syndication::Error),
 
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+
     #[error(transparent)]
     De(#[from] quick_xml::DeError),
 
+    /// Standard input is given more than once as input.
+    #[error("standard input '-' is given more than once")]
+    DuplicateStdin,
+
+    /// Invalid XMLTV datetime and the reason.
+    #[error("invalid XMLTV date and time {0:?}: {1}")]
+    InvalidDatetime(String, &'static str),
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
     #[error(transparent)]
     OptionsBuilder(#[from] crate::export::OptionsBuilderError),
 
     #[error(transparent)]
     Parse(#[from] chrono::ParseError),
 
+    /// Error at a position in the XMLTV input.
+    #[error("at {position}: {source}")]
+    Position {
+        position: Position,
+        source: Box<Error>,
+    },
+
+    /// Error in an XMLTV programme.
+    #[error("{context}: {source}")]
+    Programme {
+        context: Box<ProgrammeContext>,
+        source: Box<Error>,
+    },
+
     #[error(transparent)]
     Rss(#[from] rss::Error),
+
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+
+    /// Compressed input, but the cargo feature for its compression format is not enabled.
+    #[error("unsupported compressed input, requires feature \"{0}\"")]
+    UnsupportedCompression(&'static str),
+
+    #[error(transparent)]
+    Xml(#[from] quick_xml::Error),
+}
+
+impl Error {
+    /// Returns the underlying error, without its position or programme context.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Position { source, .. } | Error::Programme { source, .. } => source.root(),
+            _ => self,
+        }
+    }
+}
+
+/// XMLTV programme in which an error occurred.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProgrammeContext {
+    /// Index of the programme in the XMLTV input, starting at 0
+    pub index: usize,
+
+    pub channel: String,
+
+    /// Raw XMLTV start date and time
+    pub start: String,
+
+    /// Raw XMLTV stop date and time
+    pub stop: Option<String>,
+
+    /// Position of the programme in the XMLTV input, if known
+    pub position: Option<Position>,
+}
+
+impl ProgrammeContext {
+    pub fn new(programme: &Programme, index: usize, position: Option<Position>) -> Self {
+        Self {
+            index,
+            channel: programme.channel.clone(),
+            start: programme.start.clone(),
+            stop: programme.stop.clone(),
+            position,
+        }
+    }
+
+    /// Returns the error with this programme context.
+    ///
+    /// The position of the error, if any, replaces the position of the programme.
+    pub fn error(mut self, err: Error) -> Error {
+        let source = match err {
+            Error::Position { position, source } => {
+                self.position = Some(position);
+                source
+            }
+            err => Box::new(err),
+        };
+
+        Error::Programme {
+            context: Box::new(self),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for ProgrammeContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "programme {} on channel {:?} (start {:?}",
+            self.index, self.channel, self.start
+        )?;
+        if let Some(stop) = &self.stop {
+            write!(f, ", stop {stop:?}")?;
+        }
+        write!(f, ")")?;
+        if let Some(position) = self.position {
+            write!(f, " at {position}")?;
+        }
+        Ok(())
+    }
 }
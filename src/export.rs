@@ -1,11 +1,32 @@
+use chrono::{DateTime, FixedOffset, Local, TimeDelta, Utc};
+use chrono_tz::Tz;
 use derive_builder::Builder;
-use xmltv::{Programme, Tv};
+use std::borrow::Cow;
+use std::fmt::{self, Display};
+use std::io::BufRead;
+use std::str::FromStr;
+use xmltv::{Channel, Programme, Tv};
 
 pub mod atom;
+pub mod csv;
+pub mod html;
+pub mod ics;
+pub mod json;
+pub mod opml;
 pub mod rss;
 
-use crate::error::Error;
-use crate::xmltv::DEFAULT_XMLTV_DATETIME_FORMAT;
+use crate::badge::{badges, BadgePlacement};
+use crate::dedup::{Dedup, DedupAiring};
+use crate::error::{Error, ProgrammeContext};
+use crate::filter::{ChannelPattern, Filter, TimeWindow};
+use crate::infer::infer_stop_times;
+use crate::input::decompress;
+use crate::merge::MergePolicy;
+use crate::query::Query;
+use crate::sort::{sort_programmes, SortOrder};
+use crate::watchlist::Watchlist;
+use crate::xmltv::reader::{Element, Reader};
+use crate::xmltv::{find_name, find_value, parse_programme_times, DEFAULT_XMLTV_DATETIME_FORMAT};
 
 pub const DEFAULT_FEED_CHANNEL_DESCRIPTION: &str = "Generated by xmltv2rss";
 pub const DEFAULT_FEED_CHANNEL_TITLE: &str = "XMLTV feed";
@@ -32,6 +53,120 @@ pub struct Options<'a> {
     /// See [`DEFAULT_XMLTV_DATETIME_FORMAT`].
     #[builder(default = "DEFAULT_XMLTV_DATETIME_FORMAT")]
     pub xmltv_datetime_format: &'a str,
+
+    /// Timezone of XMLTV datetimes without a UTC offset. Defaults to UTC.
+    #[builder(default, setter(strip_option))]
+    pub input_timezone: Option<Tz>,
+
+    /// Timezone of the output feed dates and times.
+    /// Defaults to UTC for programme dates and times, and to the local timezone for feed dates.
+    #[builder(default, setter(strip_option))]
+    pub output_timezone: Option<Timezone>,
+
+    /// Exports only the channels and their programmes that match any of these patterns, if any.
+    #[builder(default)]
+    pub include_channels: &'a [ChannelPattern],
+
+    /// Does not export the channels and their programmes that match any of these patterns.
+    #[builder(default)]
+    pub exclude_channels: &'a [ChannelPattern],
+
+    /// Exports only programmes that stop after this time.
+    #[builder(default, setter(strip_option))]
+    pub from: Option<DateTime<FixedOffset>>,
+
+    /// Exports only programmes that start before this time.
+    #[builder(default, setter(strip_option))]
+    pub until: Option<DateTime<FixedOffset>>,
+
+    /// Exports only programmes that air from now until now plus this duration.
+    #[builder(default, setter(strip_option))]
+    pub next: Option<TimeDelta>,
+
+    /// Does not export programmes that stopped before now.
+    #[builder(default)]
+    pub drop_ended: bool,
+
+    /// The current time for [`Options::next`] and [`Options::drop_ended`], the time stamp of
+    /// iCalendar events and the [now and next view](crate::now). Defaults to the system time.
+    #[builder(default, setter(strip_option))]
+    pub now: Option<DateTime<FixedOffset>>,
+
+    /// Exports only programmes that match the watchlist, tagged with the matching rules.
+    #[builder(default, setter(strip_option))]
+    pub watchlist: Option<&'a Watchlist>,
+
+    /// Exports only programmes that match the query expression.
+    #[builder(default, setter(strip_option))]
+    pub query: Option<&'a Query>,
+
+    /// Exports only first airings: programmes marked new or premiere.
+    #[builder(default)]
+    pub only_new: bool,
+
+    /// Exports only programmes marked premiere.
+    #[builder(default)]
+    pub only_premieres: bool,
+
+    /// Does not export repeats: programmes marked previously shown.
+    #[builder(default)]
+    pub drop_repeats: bool,
+
+    /// Shows the programme badges in the item title or description. See [`badges`](crate::badge::badges).
+    #[builder(default, setter(strip_option))]
+    pub badges: Option<BadgePlacement>,
+
+    /// Collapses repeated airings of the same episode into one programme with all the airings.
+    /// See [`Dedup`](crate::dedup::Dedup). Buffers the whole TV listing in memory.
+    #[builder(default)]
+    pub dedup: bool,
+
+    /// Airing of a deduplicated episode that supplies the item publication date and GUID.
+    #[builder(default)]
+    pub dedup_airing: DedupAiring,
+
+    /// Sorts the exported programmes. Defaults to the input order. Buffers the whole TV listing in memory.
+    #[builder(default, setter(strip_option))]
+    pub sort: Option<SortOrder>,
+
+    /// Exports at most this number of programmes, after filtering and sorting.
+    #[builder(default, setter(strip_option))]
+    pub max_items: Option<usize>,
+
+    /// Exports at most this number of programmes per channel, after filtering and sorting.
+    #[builder(default, setter(strip_option))]
+    pub max_items_per_channel: Option<usize>,
+
+    /// Exports only programmes that start within this number of days,
+    /// from the start day of the earliest exported programme. Buffers the whole TV listing in memory.
+    #[builder(default, setter(strip_option))]
+    pub max_days: Option<u64>,
+
+    /// Infers missing programme stop times from the start time of the next programme on the same channel.
+    /// See [`infer_stop_times`]. Buffers the whole TV listing in memory.
+    #[builder(default)]
+    pub infer_stop_times: bool,
+
+    /// Maximum time from the start of a programme to the start of the next programme,
+    /// to infer the stop time from. Defaults to no maximum.
+    #[builder(default, setter(strip_option))]
+    pub max_stop_gap: Option<TimeDelta>,
+
+    /// Duration of programmes without a stop time or length. Defaults to zero.
+    #[builder(default, setter(strip_option))]
+    pub default_duration: Option<TimeDelta>,
+
+    /// Conflict policy for merging multiple XMLTV TV listings.
+    #[builder(default)]
+    pub merge_policy: MergePolicy,
+
+    /// Skips programmes that fail to parse, instead of failing the export.
+    #[builder(default)]
+    pub lenient: bool,
+
+    /// Receives a warning for each programme that is skipped in lenient mode.
+    #[builder(default, setter(strip_option))]
+    pub warning_handler: Option<&'a dyn Fn(&Warning)>,
 }
 
 impl Default for Options<'_> {
@@ -42,7 +177,177 @@ impl Default for Options<'_> {
             time_format: DEFAULT_FEED_TIME_FORMAT,
 
             xmltv_datetime_format: DEFAULT_XMLTV_DATETIME_FORMAT,
+            input_timezone: None,
+            output_timezone: None,
+
+            include_channels: &[],
+            exclude_channels: &[],
+
+            from: None,
+            until: None,
+            next: None,
+            drop_ended: false,
+            now: None,
+
+            watchlist: None,
+
+            query: None,
+
+            only_new: false,
+            only_premieres: false,
+            drop_repeats: false,
+            badges: None,
+
+            dedup: false,
+            dedup_airing: DedupAiring::default(),
+
+            sort: None,
+
+            max_items: None,
+            max_items_per_channel: None,
+            max_days: None,
+
+            infer_stop_times: false,
+            max_stop_gap: None,
+            default_duration: None,
+
+            merge_policy: MergePolicy::default(),
+
+            lenient: false,
+            warning_handler: None,
+        }
+    }
+}
+
+impl Options<'_> {
+    /// Returns [`Options::now`], or else the system time.
+    pub(crate) fn now(&self) -> DateTime<FixedOffset> {
+        self.now.unwrap_or_else(|| Utc::now().fixed_offset())
+    }
+
+    /// Converts a datetime to the output timezone, if specified.
+    pub(crate) fn to_output_timezone(
+        &self,
+        datetime: DateTime<FixedOffset>,
+    ) -> DateTime<FixedOffset> {
+        match self.output_timezone {
+            Some(Timezone::Local) => datetime.with_timezone(&Local).fixed_offset(),
+            Some(Timezone::Iana(timezone)) => datetime.with_timezone(&timezone).fixed_offset(),
+            None => datetime,
+        }
+    }
+}
+
+/// Output timezone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timezone {
+    /// The local timezone of the system
+    Local,
+
+    /// An IANA timezone, for example, "Europe/Berlin"
+    Iana(Tz),
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    /// Parses "local" or an IANA timezone name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("local") {
+            Ok(Timezone::Local)
+        } else {
+            s.parse()
+                .map(Timezone::Iana)
+                .map_err(|err| format!("{err}"))
+        }
+    }
+}
+
+/// Warning about a programme that is skipped in lenient mode.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    pub channel: String,
+    pub start: String,
+    pub reason: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "skipped programme on channel {:?} starting {:?}: {}",
+            self.channel, self.start, self.reason
+        )
+    }
+}
+
+/// Programme annotations, added by the export.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Annotations<'a> {
+    /// Names of the matching watchlist rules. See [`Options::watchlist`].
+    pub rules: Vec<&'a str>,
+
+    /// All the airings of a deduplicated episode, in start time order. See [`Options::dedup`].
+    pub airings: Vec<Airing>,
+}
+
+/// Airing of a programme.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Airing {
+    /// Channel id
+    pub channel: String,
+
+    /// Start time, in the output timezone
+    pub start: DateTime<FixedOffset>,
+
+    /// Stop time, in the output timezone
+    pub stop: DateTime<FixedOffset>,
+}
+
+/// Exported programme that is held back until all programmes are read, to deduplicate or sort them.
+pub(crate) struct PendingProgramme<'a> {
+    pub(crate) programme: Programme,
+    pub(crate) context: ProgrammeContext,
+
+    /// Airing of the programme, in the output timezone
+    pub(crate) airing: Airing,
+
+    pub(crate) annotations: Annotations<'a>,
+}
+
+/// Exported programmes that are held back, if they are deduplicated or sorted, or limited by day.
+/// See [`Options::dedup`], [`Options::sort`] and [`Options::max_days`].
+pub(crate) struct Pending<'a> {
+    dedup: Option<Dedup<'a>>,
+    programmes: Vec<PendingProgramme<'a>>,
+}
+
+impl<'a> Pending<'a> {
+    pub(crate) fn new(options: &Options) -> Option<Self> {
+        (options.dedup || options.sort.is_some() || options.max_days.is_some()).then(|| Self {
+            dedup: options.dedup.then(Dedup::default),
+            programmes: vec![],
+        })
+    }
+
+    pub(crate) fn add(&mut self, pending: PendingProgramme<'a>) {
+        match &mut self.dedup {
+            Some(dedup) => dedup.add(pending),
+            None => self.programmes.push(pending),
+        }
+    }
+
+    /// Returns the programmes, deduplicated and sorted.
+    pub(crate) fn finish(self, options: &Options) -> Vec<PendingProgramme<'a>> {
+        let mut programmes = match self.dedup {
+            Some(dedup) => dedup.finish(options.dedup_airing, options.now()).collect(),
+            None => self.programmes,
+        };
+        if let Some(sort) = options.sort {
+            sort_programmes(&mut programmes, sort, options);
         }
+
+        programmes
     }
 }
 
@@ -67,18 +372,18 @@ pub trait Visitor {
 
     //
 
-    // fn visit_channels_start(&mut self) -> Result<()> {
-    //     Ok(())
-    // }
-    //
-    // #[allow(unused)]
-    // fn visit_channel(&mut self, channel: &Channel) -> Result<()> {
-    //     Ok(())
-    // }
-    //
-    // fn visit_channels_end(&mut self) -> Result<()> {
-    //     Ok(())
-    // }
+    fn visit_channels_start(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    #[allow(unused)]
+    fn visit_channel(&mut self, channel: &Channel) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_channels_end(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
 
     //
 
@@ -91,6 +396,17 @@ pub trait Visitor {
         Ok(())
     }
 
+    /// Visits a programme with its annotations, for example, the matching rules of the watchlist
+    /// of [`Options::watchlist`]. Defaults to [`Visitor::visit_programme`].
+    #[allow(unused)]
+    fn visit_annotated_programme(
+        &mut self,
+        programme: &Programme,
+        annotations: &Annotations,
+    ) -> Result<(), Error> {
+        self.visit_programme(programme)
+    }
+
     fn visit_programmes_end(&mut self) -> Result<(), Error> {
         Ok(())
     }
@@ -102,24 +418,675 @@ pub trait Visitor {
 }
 
 /// Exports XMLTV data to data of type T, produced by a visitor implementation.
-pub(crate) fn export<T>(visitor: &mut impl Visitor<Output = T>, listing: &Tv) -> Result<T, Error> {
+pub(crate) fn export<T>(
+    visitor: &mut impl Visitor<Output = T>,
+    listing: &Tv,
+    options: &Options,
+) -> Result<T, Error> {
     // visitor.visit_tv_start()?;
 
     visitor.visit_tv(listing)?;
 
-    // visitor.visit_channels_start()?;
-    // for channel in &listing.channels {
-    //     visitor.visit_channel(channel)?;
-    // }
-    // visitor.visit_channels_end()?;
+    let mut filter = Filter::new(options);
+    let mut pending = Pending::new(options);
+
+    visitor.visit_channels_start()?;
+    for channel in &listing.channels {
+        if filter.accept_channel(channel) {
+            visitor.visit_channel(channel)?;
+        }
+    }
+    visitor.visit_channels_end()?;
+
+    let programmes = if options.infer_stop_times {
+        let mut programmes = listing.programmes.clone();
+        infer_stop_times(&mut programmes, options);
+        Cow::Owned(programmes)
+    } else {
+        Cow::Borrowed(listing.programmes.as_slice())
+    };
 
     visitor.visit_programmes_start()?;
-    for programme in &listing.programmes {
-        visitor.visit_programme(programme)?;
+    for (index, programme) in programmes.iter().enumerate() {
+        let context = || ProgrammeContext::new(programme, index, None);
+        visit_programme(
+            visitor,
+            programme,
+            context,
+            &mut filter,
+            &mut pending,
+            options,
+        )?;
     }
+    visit_pending(visitor, pending, &mut filter, options)?;
     visitor.visit_programmes_end()?;
 
     // visitor.visit_tv_end()?;
 
     visitor.result()
 }
+
+/// Exports XMLTV data to data of type T, produced by a visitor implementation.
+///
+/// The XMLTV data is decompressed if needed, and read and passed to the visitor one channel or programme at a time.
+pub(crate) fn export_from_reader<T>(
+    visitor: &mut impl Visitor<Output = T>,
+    reader: impl BufRead,
+    options: &Options,
+) -> Result<T, Error> {
+    let mut state = State::Start;
+    let mut reader = Reader::new(decompress(reader)?);
+    let mut filter = Filter::new(options);
+    let mut pending = Pending::new(options);
+    let mut index = 0;
+    // Programmes with their index and position, if the stop times are inferred
+    let mut programmes = vec![];
+    loop {
+        let element = match reader.read_element() {
+            Ok(Some(element)) => element,
+            Ok(None) => break,
+            Err(Error::Programme { context, source }) if !matches!(*source, Error::Xml(_)) => {
+                // Skip a programme that fails to deserialize, but not a syntax error
+                state.advance(visitor, State::Programmes)?;
+                index += 1;
+                if filter.channels.accept_channel_id(&context.channel) {
+                    skip_programme(*context, *source, options)?;
+                }
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+
+        match element {
+            Element::Tv(listing) if state == State::Start => {
+                visitor.visit_tv(&listing)?;
+                state = State::Tv;
+            }
+            Element::Tv(_) => {}
+            Element::Channel(channel) => {
+                state.advance(visitor, State::Channels)?;
+                if filter.accept_channel(&channel) {
+                    visitor.visit_channel(&channel)?;
+                }
+            }
+            Element::Programme(programme) => {
+                state.advance(visitor, State::Programmes)?;
+                let position = Some(reader.position());
+                if options.infer_stop_times {
+                    programmes.push((*programme, index, position));
+                } else {
+                    let context = || ProgrammeContext::new(&programme, index, position);
+                    visit_programme(
+                        visitor,
+                        &programme,
+                        context,
+                        &mut filter,
+                        &mut pending,
+                        options,
+                    )?;
+                }
+                index += 1;
+            }
+        }
+    }
+    state.advance(visitor, State::Programmes)?;
+
+    if options.infer_stop_times {
+        let (mut programmes, contexts): (Vec<_>, Vec<_>) = programmes
+            .into_iter()
+            .map(|(programme, index, position)| (programme, (index, position)))
+            .unzip();
+        infer_stop_times(&mut programmes, options);
+        for (programme, (index, position)) in programmes.iter().zip(contexts) {
+            let context = || ProgrammeContext::new(programme, index, position);
+            visit_programme(
+                visitor,
+                programme,
+                context,
+                &mut filter,
+                &mut pending,
+                options,
+            )?;
+        }
+    }
+    visit_pending(visitor, pending, &mut filter, options)?;
+    visitor.visit_programmes_end()?;
+
+    visitor.result()
+}
+
+/// Passes the programme to the visitor, if it is to be exported.
+///
+/// With a watchlist, only programmes that match the watchlist are passed to the visitor, with the matching rules.
+/// With deduplication or sorting, the programme is held back instead, see [`visit_pending`].
+/// Otherwise, the item limits apply.
+fn visit_programme<'a>(
+    visitor: &mut impl Visitor,
+    programme: &Programme,
+    context: impl Fn() -> ProgrammeContext,
+    filter: &mut Filter<'a>,
+    pending: &mut Option<Pending<'a>>,
+    options: &Options,
+) -> Result<(), Error> {
+    if !filter.channels.accept_channel_id(&programme.channel) || !filter.airing.accept(programme) {
+        return Ok(());
+    }
+
+    let Some((starttime_dt, stoptime_dt)) =
+        accept_programme(programme, &context, &filter.time_window, options)?
+    else {
+        return Ok(());
+    };
+    let starttime_dt = options.to_output_timezone(starttime_dt);
+    let stoptime_dt = options.to_output_timezone(stoptime_dt);
+
+    if let Some(query) = options.query {
+        if !query.is_match(programme, starttime_dt, stoptime_dt) {
+            return Ok(());
+        }
+    }
+
+    let rules = match &filter.watchlist {
+        Some(watchlist) => {
+            let rules = watchlist.matches(programme, starttime_dt);
+            if rules.is_empty() {
+                return Ok(());
+            }
+            rules
+        }
+        None => vec![],
+    };
+
+    if let Some(pending) = pending {
+        pending.add(PendingProgramme {
+            programme: programme.clone(),
+            context: context(),
+            airing: Airing {
+                channel: programme.channel.clone(),
+                start: starttime_dt,
+                stop: stoptime_dt,
+            },
+            annotations: Annotations {
+                rules,
+                ..Default::default()
+            },
+        });
+        return Ok(());
+    }
+
+    if !filter.limits.accept(&programme.channel, starttime_dt) {
+        return Ok(());
+    }
+
+    let result = if filter.watchlist.is_some() {
+        let annotations = Annotations {
+            rules,
+            ..Default::default()
+        };
+        visitor.visit_annotated_programme(programme, &annotations)
+    } else {
+        visitor.visit_programme(programme)
+    };
+
+    result.map_err(|err| context().error(err))
+}
+
+/// Passes the held back programmes to the visitor, deduplicated, sorted and within the item limits.
+fn visit_pending(
+    visitor: &mut impl Visitor,
+    pending: Option<Pending>,
+    filter: &mut Filter,
+    options: &Options,
+) -> Result<(), Error> {
+    let Some(pending) = pending else {
+        return Ok(());
+    };
+
+    let programmes = pending.finish(options);
+    filter.limits.first_day = programmes
+        .iter()
+        .map(|pending| pending.airing.start.date_naive())
+        .min();
+    for pending in programmes {
+        if !filter
+            .limits
+            .accept(&pending.airing.channel, pending.airing.start)
+        {
+            continue;
+        }
+
+        visitor
+            .visit_annotated_programme(&pending.programme, &pending.annotations)
+            .map_err(|err| pending.context.error(err))?;
+    }
+
+    Ok(())
+}
+
+/// Programme start and stop time.
+type Airtime = (DateTime<FixedOffset>, DateTime<FixedOffset>);
+
+/// Returns the programme start and stop time, if the programme is to be exported.
+fn accept_programme(
+    programme: &Programme,
+    context: impl FnOnce() -> ProgrammeContext,
+    time_window: &TimeWindow,
+    options: &Options,
+) -> Result<Option<Airtime>, Error> {
+    match parse_programme_times(programme, options) {
+        Ok((starttime_dt, stoptime_dt)) => Ok(time_window
+            .accept(starttime_dt, stoptime_dt)
+            .then_some((starttime_dt, stoptime_dt))),
+        Err(err) => skip_programme(context(), err, options).map(|_| None),
+    }
+}
+
+/// Skips a programme that fails to parse in lenient mode, and passes a warning to the warning handler.
+/// Otherwise, returns the error with the programme context.
+pub(crate) fn skip_programme(
+    context: ProgrammeContext,
+    err: Error,
+    options: &Options,
+) -> Result<(), Error> {
+    if !options.lenient {
+        return Err(context.error(err));
+    }
+
+    let warning = Warning {
+        channel: context.channel,
+        start: context.start,
+        reason: err.to_string(),
+    };
+    if let Some(warning_handler) = options.warning_handler {
+        warning_handler(&warning);
+    }
+
+    Ok(())
+}
+
+/// Returns the display name of the channel, or else the channel id.
+pub(crate) fn channel_name<'a>(xmltv_channel: &'a Channel, language: Option<&'a str>) -> &'a str {
+    let display_name = find_name(&xmltv_channel.display_names, language).trim();
+    if !display_name.is_empty() {
+        display_name
+    } else {
+        &xmltv_channel.id
+    }
+}
+
+/// Returns the display name of the channel with the id, or else the channel id.
+pub(crate) fn find_channel_name<'a>(
+    xmltv_channels: &'a [Channel],
+    language: Option<&'a str>,
+    channel_id: &'a str,
+) -> &'a str {
+    xmltv_channels
+        .iter()
+        .find(|channel| channel.id == *channel_id)
+        .map_or(channel_id, |channel| channel_name(channel, language))
+}
+
+/// Returns the item description of the RSS, Atom and JSON feeds: an HTML table with the programme title,
+/// channel, airdate, airtime, length, category and description, followed by the badges, the airings
+/// of a deduplicated episode and the matching watchlist rules, if any.
+pub(crate) fn item_description(
+    xmltv_channels: &[Channel],
+    title: &str,
+    starttime_dt: DateTime<FixedOffset>,
+    stoptime_dt: DateTime<FixedOffset>,
+    xmltv_programme: &Programme,
+    annotations: &Annotations,
+    options: &Options,
+) -> String {
+    let language = options.language.filter(|l| !l.is_empty());
+
+    let channel = channel_label(xmltv_channels, language, &xmltv_programme.channel);
+
+    let airdate = format!("{}", starttime_dt.format(options.date_format));
+    let airtime = format!(
+        "{} - {}",
+        starttime_dt.format(options.time_format),
+        stoptime_dt.format(options.time_format)
+    );
+
+    let airtime_length_td = stoptime_dt - starttime_dt;
+    let airtime_length_mins = airtime_length_td.num_seconds() / 60;
+    let airtime_length = format!(
+        "{:02}:{:02}:00",
+        airtime_length_mins / 60,
+        airtime_length_mins % 60
+    );
+
+    let category = find_name(&xmltv_programme.categories, language);
+
+    let desc = find_value(&xmltv_programme.descriptions, language);
+    let desc = desc
+        .trim()
+        .lines()
+        .map(|line| line.trim())
+        .collect::<Vec<_>>()
+        .join("<br/>");
+
+    let badges = if options.badges == Some(BadgePlacement::Description) {
+        badges(xmltv_programme, options)
+    } else {
+        vec![]
+    };
+    let badges = if !badges.is_empty() {
+        format!(
+            "<tr><td align=\"right\" valign=\"top\">Badges:</td><td>{}</td></tr>",
+            badges.join(", ")
+        )
+    } else {
+        String::new()
+    };
+
+    let airings = airings_row(xmltv_channels, language, &annotations.airings, options);
+
+    let watchlist = if !annotations.rules.is_empty() {
+        format!(
+            "<tr><td align=\"right\" valign=\"top\">Watchlist:</td><td>{}</td></tr>",
+            annotations.rules.join(", ")
+        )
+    } else {
+        String::new()
+    };
+
+    format!("\
+<table>\
+<tr><td align=\"right\" valign=\"top\">Title:</td><td>{title}</td></tr>\
+<tr><td align=\"right\" valign=\"top\">Channel:</td><td>{channel}</td></tr>\
+<tr><td align=\"right\" valign=\"top\">Airdate:</td><td>{airdate}</td></tr>\
+<tr><td align=\"right\" valign=\"top\">Airtime:</td><td>{airtime}</td></tr>\
+<tr><td align=\"right\" valign=\"top\" style=\"white-space: nowrap\">Length:</td><td>{airtime_length}</td></tr>\
+<tr><td align=\"right\" valign=\"top\">Category:</td><td>{category}</td></tr>\
+<tr><td align=\"right\" valign=\"top\">Description:</td><td>{desc}</td></tr>\
+{badges}\
+{airings}\
+{watchlist}\
+</table>")
+}
+
+/// Returns the channel id with the display name of the channel, if known, for the item descriptions.
+fn channel_label(xmltv_channels: &[Channel], language: Option<&str>, channel_id: &str) -> String {
+    match xmltv_channels
+        .iter()
+        .find(|channel| channel.id == *channel_id)
+    {
+        Some(channel) => {
+            let display_name = find_name(&channel.display_names, language);
+            format!("{channel_id}-{display_name}")
+        }
+        None => channel_id.to_string(),
+    }
+}
+
+/// Returns the "Airings" row of the item description table, with the channel, date and time of each airing
+/// of a deduplicated episode. Returns an empty string for a single airing.
+fn airings_row(
+    xmltv_channels: &[Channel],
+    language: Option<&str>,
+    airings: &[Airing],
+    options: &Options,
+) -> String {
+    if airings.len() <= 1 {
+        return String::new();
+    }
+
+    let airings = airings
+        .iter()
+        .map(|airing| {
+            format!(
+                "{} {} {} - {}",
+                channel_label(xmltv_channels, language, &airing.channel),
+                airing.start.format(options.date_format),
+                airing.start.format(options.time_format),
+                airing.stop.format(options.time_format)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("<br/>");
+
+    format!("<tr><td align=\"right\" valign=\"top\">Airings:</td><td>{airings}</td></tr>")
+}
+
+/// Streaming export state. The XMLTV DTD places all channels before all programmes.
+#[derive(PartialEq, PartialOrd)]
+enum State {
+    Start,
+    Tv,
+    Channels,
+    Programmes,
+}
+
+impl State {
+    /// Calls the visitor methods between the current and the next state.
+    fn advance<T>(
+        &mut self,
+        visitor: &mut impl Visitor<Output = T>,
+        next: State,
+    ) -> Result<(), Error> {
+        while *self < next {
+            *self = match self {
+                State::Start => {
+                    visitor.visit_tv(&Tv::default())?;
+                    State::Tv
+                }
+                State::Tv => {
+                    visitor.visit_channels_start()?;
+                    State::Channels
+                }
+                State::Channels | State::Programmes => {
+                    visitor.visit_channels_end()?;
+                    visitor.visit_programmes_start()?;
+                    State::Programmes
+                }
+            };
+        }
+
+        Ok(())
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use quick_xml::de;
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::xmltv::reader::{Position, Reader};
+
+    const INPUT: &str = r#"<tv>
+  <programme channel="a" start="20240101100000 +0000"><title>First</title></programme>
+  <programme channel="b" start="2024010199"><title>Invalid</title></programme>
+  <programme channel="a" start="20240101110000 +0000"><title>Last</title></programme>
+</tv>"#;
+
+    /// Collects the programme start times.
+    #[derive(Default)]
+    struct Starts(Vec<String>);
+
+    impl Visitor for Starts {
+        type Output = Vec<String>;
+
+        fn visit_programme(&mut self, programme: &Programme) -> Result<(), Error> {
+            self.0.push(programme.start.clone());
+
+            Ok(())
+        }
+
+        fn result(&self) -> Result<Self::Output, Error> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn test_lenient() {
+        let warnings = RefCell::new(vec![]);
+        let warning_handler = |warning: &Warning| warnings.borrow_mut().push(warning.clone());
+        let options = Options {
+            lenient: true,
+            warning_handler: Some(&warning_handler),
+            ..Default::default()
+        };
+
+        let starts =
+            export_from_reader(&mut Starts::default(), INPUT.as_bytes(), &options).unwrap();
+        assert_eq!(starts, ["20240101100000 +0000", "20240101110000 +0000"]);
+        assert_eq!(
+            warnings.into_inner(),
+            [Warning {
+                channel: "b".to_string(),
+                start: "2024010199".to_string(),
+                reason: "invalid XMLTV date and time \"2024010199\": time out of range".to_string(),
+            }]
+        );
+
+        let result = export_from_reader(
+            &mut Starts::default(),
+            INPUT.as_bytes(),
+            &Options::default(),
+        );
+        assert!(matches!(
+            result.as_ref().map_err(Error::root),
+            Err(Error::InvalidDatetime(..))
+        ));
+    }
+
+    #[test]
+    fn test_query() {
+        let input = r#"<tv>
+  <programme channel="a" start="20240101100000 +0000"><title>First</title><previously-shown/></programme>
+  <programme channel="a" start="20240101110000 +0000"><title>Last</title></programme>
+</tv>"#;
+        let query = "not previously_shown".parse().unwrap();
+        let options = Options {
+            query: Some(&query),
+            ..Default::default()
+        };
+
+        let starts =
+            export_from_reader(&mut Starts::default(), input.as_bytes(), &options).unwrap();
+        assert_eq!(starts, ["20240101110000 +0000"]);
+
+        let listing = Reader::new(input.as_bytes()).read_listing().unwrap();
+        let starts = export(&mut Starts::default(), &listing, &options).unwrap();
+        assert_eq!(starts, ["20240101110000 +0000"]);
+    }
+
+    #[test]
+    fn test_limits() {
+        let input = r#"<tv>
+  <programme channel="a" start="20240101100000 +0000"><title>A1</title></programme>
+  <programme channel="a" start="20240101120000 +0000"><title>A2</title></programme>
+  <programme channel="b" start="20240101110000 +0000"><title>B1</title></programme>
+  <programme channel="b" start="20240102110000 +0000"><title>B2</title></programme>
+</tv>"#;
+        let tests = [
+            (
+                Options {
+                    max_items: Some(2),
+                    ..Default::default()
+                },
+                vec!["20240101100000 +0000", "20240101120000 +0000"],
+            ),
+            (
+                Options {
+                    max_items: Some(2),
+                    sort: Some(SortOrder::Start),
+                    ..Default::default()
+                },
+                vec!["20240101100000 +0000", "20240101110000 +0000"],
+            ),
+            (
+                Options {
+                    max_items_per_channel: Some(1),
+                    sort: Some(SortOrder::Reverse),
+                    ..Default::default()
+                },
+                vec!["20240102110000 +0000", "20240101120000 +0000"],
+            ),
+            (
+                Options {
+                    max_days: Some(1),
+                    ..Default::default()
+                },
+                vec![
+                    "20240101100000 +0000",
+                    "20240101120000 +0000",
+                    "20240101110000 +0000",
+                ],
+            ),
+        ];
+        for (options, expected) in tests {
+            let starts =
+                export_from_reader(&mut Starts::default(), input.as_bytes(), &options).unwrap();
+            assert_eq!(starts, expected);
+
+            let listing = Reader::new(input.as_bytes()).read_listing().unwrap();
+            let starts = export(&mut Starts::default(), &listing, &options).unwrap();
+            assert_eq!(starts, expected);
+        }
+    }
+
+    #[test]
+    fn test_error_context() {
+        let expected = ProgrammeContext {
+            index: 1,
+            channel: "b".to_string(),
+            start: "2024010199".to_string(),
+            stop: None,
+            position: Some(Position {
+                line: 3,
+                offset: 94,
+            }),
+        };
+
+        let result = export_from_reader(
+            &mut Starts::default(),
+            INPUT.as_bytes(),
+            &Options::default(),
+        );
+        match result {
+            Err(Error::Programme { context, .. }) => assert_eq!(*context, expected),
+            _ => panic!("expected programme error"),
+        }
+
+        let listing = de::from_str(INPUT).unwrap();
+        let result = export(&mut Starts::default(), &listing, &Options::default());
+        match result {
+            Err(Error::Programme { context, .. }) => assert_eq!(
+                *context,
+                ProgrammeContext {
+                    position: None,
+                    ..expected
+                }
+            ),
+            _ => panic!("expected programme error"),
+        }
+
+        // Syntax error in a channel
+        let input = "<tv>\n  <channel id=\"a\">\n    <display-name>A</name>\n  </channel>\n</tv>";
+        let result = export_from_reader(
+            &mut Starts::default(),
+            input.as_bytes(),
+            &Options::default(),
+        );
+        match result {
+            Err(Error::Position { position, source }) => {
+                assert_eq!(
+                    position,
+                    Position {
+                        line: 3,
+                        offset: 43,
+                    }
+                );
+                assert!(matches!(*source, Error::Xml(_)));
+            }
+            _ => panic!("expected positioned error"),
+        }
+    }
+}
@@ -1,19 +1,21 @@
 use atom_syndication::{
-    Entry, EntryBuilder, Feed, FeedBuilder, GeneratorBuilder, LinkBuilder, Text,
+    CategoryBuilder, Entry, EntryBuilder, Feed, FeedBuilder, GeneratorBuilder, LinkBuilder, Text,
 };
-use chrono::{DateTime, FixedOffset, Local};
-use quick_xml::de::from_str;
-use std::fs;
+use chrono::{DateTime, Local};
 use std::hash::{DefaultHasher, Hash, Hasher};
-use std::io;
+use std::io::{BufReader, Read};
+use std::path::Path;
 use uuid::Uuid;
 use xmltv::{Channel, Programme, Tv};
 
+use crate::badge::{badges, title_with_badges, BadgePlacement};
 use crate::error::Error;
-use crate::export::{Options, Visitor, GUID_DATETIME_FORMAT};
-use crate::export::{DEFAULT_FEED_CHANNEL_DESCRIPTION, DEFAULT_FEED_CHANNEL_TITLE};
-use crate::xmltv::DEFAULT_XMLTV_DATETIME_FORMAT_UTC;
-use crate::xmltv::{find_name, find_value, first_url, parse_from_str};
+use crate::export::{item_description, Annotations, Options, Visitor};
+use crate::export::{
+    DEFAULT_FEED_CHANNEL_DESCRIPTION, DEFAULT_FEED_CHANNEL_TITLE, GUID_DATETIME_FORMAT,
+};
+use crate::input;
+use crate::xmltv::{find_value, first_url, parse_programme_times};
 
 /// Exports an XMLTV TV listing to an Atom feed.
 pub fn export(
@@ -21,29 +23,58 @@ pub fn export(
     link: &str,
     subtitle: Option<&str>,
     options: &Options,
-    // reader: &mut impl Read,
     file: Option<&str>,
 ) -> Result<Feed, Error> {
-    let last_build_date = Local::now();
-    let (xmltv_listing, updated) = match &file {
-        Some(file) if *file != "-" => (
-            fs::read_to_string(file)?,
-            DateTime::<Local>::from(fs::metadata(file)?.modified()?),
-        ),
-        _ => (io::read_to_string(io::stdin())?, last_build_date),
-    };
-    let xmltv_listing: Tv = from_str(&xmltv_listing)?;
-
-    let mut visitor = Atom::new(
+    let (reader, updated) = input::open(file.map(Path::new))?;
+
+    export_from_reader(title, link, subtitle, Some(updated), options, reader)
+}
+
+/// Exports an XMLTV TV listing, read from a reader, to an Atom feed.
+pub fn export_from_reader(
+    title: &str,
+    link: &str,
+    subtitle: Option<&str>,
+    updated: Option<DateTime<Local>>,
+    options: &Options,
+    reader: impl Read,
+) -> Result<Feed, Error> {
+    let mut visitor = Atom::new(title, link, subtitle, updated, options);
+
+    super::export_from_reader::<Feed>(&mut visitor, BufReader::new(reader), options)
+}
+
+/// Exports an XMLTV TV listing string to an Atom feed.
+pub fn export_from_str(
+    title: &str,
+    link: &str,
+    subtitle: Option<&str>,
+    updated: Option<DateTime<Local>>,
+    options: &Options,
+    xmltv_listing: &str,
+) -> Result<Feed, Error> {
+    export_from_reader(
         title,
         link,
         subtitle,
-        Some(updated),
+        updated,
         options,
-        &xmltv_listing.channels,
-    );
+        xmltv_listing.as_bytes(),
+    )
+}
+
+/// Exports a parsed XMLTV TV listing to an Atom feed.
+pub fn export_from_tv(
+    title: &str,
+    link: &str,
+    subtitle: Option<&str>,
+    updated: Option<DateTime<Local>>,
+    options: &Options,
+    xmltv_listing: &Tv,
+) -> Result<Feed, Error> {
+    let mut visitor = Atom::new(title, link, subtitle, updated, options);
 
-    super::export::<Feed>(&mut visitor, &xmltv_listing)
+    super::export::<Feed>(&mut visitor, xmltv_listing, options)
 }
 
 //
@@ -57,7 +88,7 @@ pub(crate) struct Atom<'a> {
     options: &'a Options<'a>,
 
     // Visitor state
-    xmltv_channels: &'a Vec<Channel>,
+    xmltv_channels: Vec<Channel>,
     feed: FeedBuilder,
     entries: Vec<Entry>,
 }
@@ -69,9 +100,6 @@ impl<'a> Atom<'a> {
         subtitle: Option<&'a str>,
         updated: Option<DateTime<Local>>,
         options: &'a Options,
-
-        // Input data
-        xmltv_channels: &'a Vec<Channel>,
     ) -> Self {
         let title = if !title.is_empty() {
             title
@@ -87,70 +115,11 @@ impl<'a> Atom<'a> {
             options,
 
             // Visitor state
-            xmltv_channels,
+            xmltv_channels: vec![],
             feed: FeedBuilder::default(),
             entries: vec![],
         }
     }
-
-    fn entry_summary(
-        &mut self,
-        language: Option<&str>,
-        title: &str,
-        channel_id: &str,
-        starttime_dt: DateTime<FixedOffset>,
-        stoptime_dt: DateTime<FixedOffset>,
-        xmltv_programme: &Programme,
-    ) -> Result<String, Error> {
-        let channel = if let Some(channel_callsign) = self
-            .xmltv_channels
-            .iter()
-            .find(|channel| channel.id == *channel_id)
-        {
-            let display_name = find_name(&channel_callsign.display_names, language);
-            format!("{channel_id}-{display_name}")
-        } else {
-            channel_id.to_string()
-        };
-
-        let airdate = format!("{}", starttime_dt.format(self.options.date_format));
-        let airtime = format!(
-            "{} - {}",
-            starttime_dt.format(self.options.time_format),
-            stoptime_dt.format(self.options.time_format)
-        );
-
-        let airtime_length_td = stoptime_dt - starttime_dt;
-        let airtime_length_mins = airtime_length_td.num_seconds() / 60;
-        let airtime_length = format!(
-            "{:02}:{:02}:00",
-            airtime_length_mins / 60,
-            airtime_length_mins % 60
-        );
-
-        let category = find_name(&xmltv_programme.categories, language);
-
-        let desc = find_value(&xmltv_programme.descriptions, language);
-        let desc = desc
-            .trim()
-            .lines()
-            .map(|line| line.trim())
-            .collect::<Vec<_>>()
-            .join("<br/>");
-
-        let summary = format!("\
-<table>\
-<tr><td align=\"right\" valign=\"top\">Title:</td><td>{title}</td></tr>\
-<tr><td align=\"right\" valign=\"top\">Channel:</td><td>{channel}</td></tr>\
-<tr><td align=\"right\" valign=\"top\">Airdate:</td><td>{airdate}</td></tr>\
-<tr><td align=\"right\" valign=\"top\">Airtime:</td><td>{airtime}</td></tr>\
-<tr><td align=\"right\" valign=\"top\" style=\"white-space: nowrap\">Length:</td><td>{airtime_length}</td></tr>\
-<tr><td align=\"right\" valign=\"top\">Category:</td><td>{category}</td></tr>\
-<tr><td align=\"right\" valign=\"top\">Description:</td><td>{desc}</td></tr>\
-</table>");
-
-        Ok(summary)
-    }
 }
 
 impl Visitor for Atom<'_> {
@@ -174,12 +143,25 @@ impl Visitor for Atom<'_> {
             self.feed.lang(language.to_string());
         }
         if let Some(updated) = self.updated {
-            self.feed.updated(updated);
+            self.feed
+                .updated(self.options.to_output_timezone(updated.fixed_offset()));
         }
 
         Ok(())
     }
 
+    fn visit_channels_start(&mut self) -> Result<(), Error> {
+        self.xmltv_channels.clear();
+
+        Ok(())
+    }
+
+    fn visit_channel(&mut self, xmltv_channel: &Channel) -> Result<(), Error> {
+        self.xmltv_channels.push(xmltv_channel.clone());
+
+        Ok(())
+    }
+
     fn visit_programmes_start(&mut self) -> Result<(), Error> {
         self.entries.clear();
 
@@ -188,55 +170,69 @@ impl Visitor for Atom<'_> {
 
     /// Exports from XMLTV programme to Atom entry.
     fn visit_programme(&mut self, xmltv_programme: &Programme) -> Result<(), Error> {
+        self.visit_annotated_programme(xmltv_programme, &Annotations::default())
+    }
+
+    /// Exports from XMLTV programme to Atom entry, with a category for each matching watchlist rule
+    /// and the airings of a deduplicated episode.
+    fn visit_annotated_programme(
+        &mut self,
+        xmltv_programme: &Programme,
+        annotations: &Annotations,
+    ) -> Result<(), Error> {
         // let language = self.options.language;
         let language = self.options.language.filter(|l| !l.is_empty());
 
         let channel_id = &xmltv_programme.channel;
 
-        let starttime = &xmltv_programme.start;
-        let stoptime = match &xmltv_programme.stop {
-            Some(time) => time,
-            None => starttime,
-        };
-
-        let starttime_dt = parse_from_str(
-            starttime,
-            self.options.xmltv_datetime_format,
-            DEFAULT_XMLTV_DATETIME_FORMAT_UTC,
-        )?;
-        let stoptime_dt = parse_from_str(
-            stoptime,
-            self.options.xmltv_datetime_format,
-            DEFAULT_XMLTV_DATETIME_FORMAT_UTC,
-        )?;
+        let (starttime_dt, stoptime_dt) = parse_programme_times(xmltv_programme, self.options)?;
+        let starttime_dt = self.options.to_output_timezone(starttime_dt);
+        let stoptime_dt = self.options.to_output_timezone(stoptime_dt);
 
         //
 
         let title = find_value(&xmltv_programme.titles, language);
+        let badge_title = match self.options.badges {
+            Some(BadgePlacement::Title) => {
+                title_with_badges(title, &badges(xmltv_programme, self.options))
+            }
+            _ => title.to_string(),
+        };
 
         let link = first_url(&xmltv_programme.urls).unwrap_or_default();
 
-        let summary = self.entry_summary(
-            language,
+        let summary = item_description(
+            &self.xmltv_channels,
             title,
-            channel_id,
             starttime_dt,
             stoptime_dt,
             xmltv_programme,
-        )?;
+            annotations,
+            self.options,
+        );
 
-        let hash_data = format!("{channel_id}-{}", starttime_dt.format(GUID_DATETIME_FORMAT));
+        let hash_data = format!(
+            "{channel_id}-{}",
+            starttime_dt.to_utc().format(GUID_DATETIME_FORMAT)
+        );
         let uuid = uuid(hash_data.as_bytes());
 
         let published = starttime_dt;
 
         let entry = EntryBuilder::default()
-            .title(title.to_string())
+            .title(badge_title)
             .link(LinkBuilder::default().href(link).build())
             .summary(Text::plain(summary))
             .id(format!("urn:uuid:{uuid}"))
             .published(published)
             .updated(published)
+            .categories(
+                annotations
+                    .rules
+                    .iter()
+                    .map(|rule| CategoryBuilder::default().term(rule.to_string()).build())
+                    .collect::<Vec<_>>(),
+            )
             .build();
 
         self.entries.push(entry);
@@ -271,12 +267,13 @@ fn uuid(hash_data: &[u8]) -> Uuid {
 #[cfg(test)]
 mod tests {
     use atom_syndication::WriteConfig;
+    use chrono_tz::Tz;
     use pretty_assertions::assert_eq;
     use quick_xml::de::from_str;
     use std::fs;
 
     use super::*;
-    use crate::export;
+    use crate::export::{self, Timezone};
 
     const DEFAULT_XML_INDENT: usize = 2;
     const UPDATED: &str = "Tue, 30 Apr 2024 12:00:00 +0000";
@@ -286,26 +283,48 @@ mod tests {
         input_file: &'a str,
         expected_file: &'a str,
         language: Option<&'a str>,
+        input_timezone: Option<Tz>,
+        output_timezone: Option<Timezone>,
     }
 
     #[test]
     fn test() {
         // const TESTS: [Test; _] = [Test {
-        const TESTS: [Test; 3] = [
+        const TESTS: [Test; 5] = [
             Test {
                 input_file: "tests/input/simple.xml",
                 expected_file: "tests/output/atom/simple.xml",
                 language: None,
+                input_timezone: None,
+                output_timezone: None,
             },
             Test {
                 input_file: "tests/input/simple.xml",
                 expected_file: "tests/output/atom/simple-language.xml",
                 language: Some("fr-FR"),
+                input_timezone: None,
+                output_timezone: None,
             },
             Test {
                 input_file: "tests/input/timezones.xml",
                 expected_file: "tests/output/atom/timezones.xml",
                 language: None,
+                input_timezone: None,
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/atom/timezones-input-timezone.xml",
+                language: None,
+                input_timezone: Some(chrono_tz::Europe::Amsterdam),
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/atom/timezones-output-timezone.xml",
+                language: None,
+                input_timezone: None,
+                output_timezone: Some(Timezone::Iana(chrono_tz::Europe::Berlin)),
             },
         ];
 
@@ -330,6 +349,8 @@ mod tests {
             // let options = Options::default();
             let options = Options {
                 language: test.language,
+                input_timezone: test.input_timezone,
+                output_timezone: test.output_timezone,
                 ..Default::default()
             };
             let xmltv_listing: Tv = from_str(&input).unwrap();
@@ -341,9 +362,21 @@ mod tests {
                 subtitle,
                 Some(updated.into()),
                 &options,
-                &xmltv_listing.channels,
             );
-            let feed = export::export::<Feed>(&mut visitor, &xmltv_listing).unwrap();
+            let feed = export::export::<Feed>(&mut visitor, &xmltv_listing, &options).unwrap();
+
+            // Run test with the streaming XMLTV reader
+            let mut visitor = Atom::new(
+                DEFAULT_FEED_CHANNEL_TITLE,
+                link,
+                subtitle,
+                Some(updated.into()),
+                &options,
+            );
+            let streamed_feed =
+                export::export_from_reader::<Feed>(&mut visitor, input.as_bytes(), &options)
+                    .unwrap();
+            assert_eq!(streamed_feed, feed, "for streamed input file {input_file}");
 
             let config = WriteConfig {
                 indent_size: DEFAULT_XML_INDENT.into(),
@@ -0,0 +1,469 @@
+use ::csv::{Terminator, WriterBuilder};
+use clap::ValueEnum;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use xmltv::{Programme, Tv};
+
+use crate::badge::{badges, title_with_badges, BadgePlacement};
+use crate::error::Error;
+use crate::export::{find_channel_name, Annotations, Options, Visitor};
+use crate::input;
+use crate::xmltv::{find_value, parse_programme_times};
+
+/// Delimited text format.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TableFormat {
+    /// Comma-separated values, with CRLF line breaks. See RFC 4180.
+    #[default]
+    Csv,
+
+    /// Tab-separated values, with LF line breaks
+    Tsv,
+}
+
+/// Column of the delimited text output.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TableColumn {
+    /// Channel id
+    ChannelId,
+
+    /// Channel display name, or else the channel id
+    ChannelName,
+
+    /// Start date and time, in the date and time formats
+    Start,
+
+    /// Stop date and time, in the date and time formats
+    Stop,
+
+    /// Duration, as hours, minutes and seconds
+    Duration,
+
+    Title,
+
+    SubTitle,
+
+    /// Categories, separated by ", "
+    Categories,
+
+    /// Onscreen episode number, or else the "xmltv_ns" episode number as "S01E02"
+    Episode,
+
+    /// Description, with whitespace collapsed
+    Description,
+}
+
+impl TableColumn {
+    /// All columns, in default order.
+    pub const ALL: [TableColumn; 10] = [
+        TableColumn::ChannelId,
+        TableColumn::ChannelName,
+        TableColumn::Start,
+        TableColumn::Stop,
+        TableColumn::Duration,
+        TableColumn::Title,
+        TableColumn::SubTitle,
+        TableColumn::Categories,
+        TableColumn::Episode,
+        TableColumn::Description,
+    ];
+
+    /// Returns the column name of the header row.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TableColumn::ChannelId => "channel-id",
+            TableColumn::ChannelName => "channel-name",
+            TableColumn::Start => "start",
+            TableColumn::Stop => "stop",
+            TableColumn::Duration => "duration",
+            TableColumn::Title => "title",
+            TableColumn::SubTitle => "sub-title",
+            TableColumn::Categories => "categories",
+            TableColumn::Episode => "episode",
+            TableColumn::Description => "description",
+        }
+    }
+}
+
+/// Exports an XMLTV TV listing to a table, with a row per programme.
+///
+/// If there are no columns, then the table has all the columns.
+pub fn export(
+    format: TableFormat,
+    columns: &[TableColumn],
+    options: &Options,
+    file: Option<&str>,
+) -> Result<Table, Error> {
+    let (reader, _) = input::open(file.map(Path::new))?;
+
+    export_from_reader(format, columns, options, reader)
+}
+
+/// Exports an XMLTV TV listing, read from a reader, to a table.
+pub fn export_from_reader(
+    format: TableFormat,
+    columns: &[TableColumn],
+    options: &Options,
+    reader: impl Read,
+) -> Result<Table, Error> {
+    let mut visitor = Csv::new(format, columns, options);
+
+    super::export_from_reader::<Table>(&mut visitor, BufReader::new(reader), options)
+}
+
+/// Exports an XMLTV TV listing string to a table.
+pub fn export_from_str(
+    format: TableFormat,
+    columns: &[TableColumn],
+    options: &Options,
+    xmltv_listing: &str,
+) -> Result<Table, Error> {
+    export_from_reader(format, columns, options, xmltv_listing.as_bytes())
+}
+
+/// Exports a parsed XMLTV TV listing to a table.
+pub fn export_from_tv(
+    format: TableFormat,
+    columns: &[TableColumn],
+    options: &Options,
+    xmltv_listing: &Tv,
+) -> Result<Table, Error> {
+    let mut visitor = Csv::new(format, columns, options);
+
+    super::export::<Table>(&mut visitor, xmltv_listing, options)
+}
+
+//
+
+/// Table with a row per programme, written as delimited text with a header row.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Table {
+    pub format: TableFormat,
+    pub columns: Vec<TableColumn>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Writes the header row and the rows. Fields with delimiters, quotes or line breaks are quoted.
+    pub fn write_to(&self, writer: impl Write) -> Result<(), Error> {
+        let (delimiter, terminator) = match self.format {
+            TableFormat::Csv => (b',', Terminator::CRLF),
+            TableFormat::Tsv => (b'\t', Terminator::Any(b'\n')),
+        };
+        let mut writer = WriterBuilder::new()
+            .delimiter(delimiter)
+            .terminator(terminator)
+            .from_writer(writer);
+
+        writer.write_record(self.columns.iter().map(TableColumn::name))?;
+        for row in &self.rows {
+            writer.write_record(row)?;
+        }
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Returns the onscreen episode number, or else the "xmltv_ns" episode number as "S01E02",
+/// or else the first episode number.
+fn episode(programme: &Programme) -> String {
+    let find = |system: &str| {
+        programme
+            .episode_num
+            .iter()
+            .find(|episode_num| episode_num.system.eq_ignore_ascii_case(system))
+    };
+
+    if let Some(onscreen) = find("onscreen") {
+        return onscreen.value.trim().to_string();
+    }
+    if let Some(xmltv_ns) = find("xmltv_ns") {
+        // Zero-based "season/total.episode/total.part/total", with optional parts.
+        // A number that is too large to be one-based is shown as is.
+        let numbers: Option<Vec<Option<u32>>> = xmltv_ns
+            .value
+            .split('.')
+            .map(|part| {
+                let number = part.split('/').next().unwrap_or_default().trim();
+                match number.parse::<u32>() {
+                    Ok(number) => number.checked_add(1).map(Some),
+                    Err(_) => Some(None),
+                }
+            })
+            .collect();
+        let Some(numbers) = numbers else {
+            return xmltv_ns.value.trim().to_string();
+        };
+        match numbers.as_slice() {
+            [Some(season), Some(episode), ..] => return format!("S{season:02}E{episode:02}"),
+            [None, Some(episode), ..] => return format!("E{episode:02}"),
+            [Some(season), ..] => return format!("S{season:02}"),
+            _ => {}
+        }
+    }
+
+    programme
+        .episode_num
+        .first()
+        .map(|episode_num| episode_num.value.trim().to_string())
+        .unwrap_or_default()
+}
+
+//
+
+/// Delimited text export struct.
+pub(crate) struct Csv<'a> {
+    format: TableFormat,
+    columns: &'a [TableColumn],
+    options: &'a Options<'a>,
+
+    // Visitor state
+    xmltv_channels: Vec<xmltv::Channel>,
+    rows: Vec<Vec<String>>,
+}
+
+impl<'a> Csv<'a> {
+    pub fn new(format: TableFormat, columns: &'a [TableColumn], options: &'a Options) -> Self {
+        let columns = if !columns.is_empty() {
+            columns
+        } else {
+            &TableColumn::ALL
+        };
+
+        Self {
+            format,
+            columns,
+            options,
+
+            // Visitor state
+            xmltv_channels: vec![],
+            rows: vec![],
+        }
+    }
+}
+
+impl Visitor for Csv<'_> {
+    type Output = Table;
+
+    fn visit_channels_start(&mut self) -> Result<(), Error> {
+        self.xmltv_channels.clear();
+
+        Ok(())
+    }
+
+    fn visit_channel(&mut self, xmltv_channel: &xmltv::Channel) -> Result<(), Error> {
+        self.xmltv_channels.push(xmltv_channel.clone());
+
+        Ok(())
+    }
+
+    fn visit_programmes_start(&mut self) -> Result<(), Error> {
+        self.rows.clear();
+
+        Ok(())
+    }
+
+    /// Exports from XMLTV programme to table row.
+    fn visit_programme(&mut self, xmltv_programme: &Programme) -> Result<(), Error> {
+        self.visit_annotated_programme(xmltv_programme, &Annotations::default())
+    }
+
+    /// Exports from XMLTV programme to table row. The categories include the matching watchlist rules.
+    fn visit_annotated_programme(
+        &mut self,
+        xmltv_programme: &Programme,
+        annotations: &Annotations,
+    ) -> Result<(), Error> {
+        let language = self.options.language.filter(|l| !l.is_empty());
+
+        let channel_id = &xmltv_programme.channel;
+
+        let (starttime_dt, stoptime_dt) = parse_programme_times(xmltv_programme, self.options)?;
+        let starttime_dt = self.options.to_output_timezone(starttime_dt);
+        let stoptime_dt = self.options.to_output_timezone(stoptime_dt);
+        let datetime_format = format!("{} {}", self.options.date_format, self.options.time_format);
+
+        //
+
+        let row = self
+            .columns
+            .iter()
+            .map(|column| match column {
+                TableColumn::ChannelId => channel_id.clone(),
+                TableColumn::ChannelName => {
+                    find_channel_name(&self.xmltv_channels, language, channel_id).to_string()
+                }
+                TableColumn::Start => starttime_dt.format(&datetime_format).to_string(),
+                TableColumn::Stop => stoptime_dt.format(&datetime_format).to_string(),
+                TableColumn::Duration => {
+                    let seconds = (stoptime_dt - starttime_dt).num_seconds();
+                    format!(
+                        "{:02}:{:02}:{:02}",
+                        seconds / 3600,
+                        seconds / 60 % 60,
+                        seconds % 60
+                    )
+                }
+                TableColumn::Title => {
+                    let title = find_value(&xmltv_programme.titles, language);
+                    match self.options.badges {
+                        Some(BadgePlacement::Title) => {
+                            title_with_badges(title, &badges(xmltv_programme, self.options))
+                        }
+                        _ => title.to_string(),
+                    }
+                }
+                TableColumn::SubTitle => find_value(&xmltv_programme.sub_titles, language)
+                    .trim()
+                    .to_string(),
+                TableColumn::Categories => {
+                    let mut categories: Vec<&str> = vec![];
+                    let programme_categories = xmltv_programme
+                        .categories
+                        .iter()
+                        .filter(|category| {
+                            language.is_none() || category.lang.as_deref() == language
+                        })
+                        .map(|category| category.name.trim());
+                    for category in programme_categories.chain(annotations.rules.iter().copied()) {
+                        if !category.is_empty() && !categories.contains(&category) {
+                            categories.push(category);
+                        }
+                    }
+                    categories.join(", ")
+                }
+                TableColumn::Episode => episode(xmltv_programme),
+                TableColumn::Description => find_value(&xmltv_programme.descriptions, language)
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            })
+            .collect();
+
+        self.rows.push(row);
+
+        Ok(())
+    }
+
+    /// Returns the exported table.
+    fn result(&self) -> Result<Self::Output, Error> {
+        Ok(Table {
+            format: self.format,
+            columns: self.columns.to_vec(),
+            rows: self.rows.clone(),
+        })
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use xmltv::EpisodeNum;
+
+    use super::*;
+    use crate::export::{self, Timezone};
+    use crate::xmltv::reader::Reader;
+
+    #[derive(Default)]
+    struct Test<'a> {
+        input_file: &'a str,
+        expected_file: &'a str,
+        format: TableFormat,
+        columns: &'a [TableColumn],
+        output_timezone: Option<Timezone>,
+    }
+
+    #[test]
+    fn test() {
+        const TESTS: [Test; 3] = [
+            Test {
+                input_file: "tests/input/simple.xml",
+                expected_file: "tests/output/csv/simple.csv",
+                format: TableFormat::Csv,
+                columns: &[],
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/csv/timezones.tsv",
+                format: TableFormat::Tsv,
+                columns: &[],
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/csv/timezones-output-timezone.csv",
+                format: TableFormat::Csv,
+                columns: &[
+                    TableColumn::Title,
+                    TableColumn::Start,
+                    TableColumn::Duration,
+                ],
+                output_timezone: Some(Timezone::Iana(chrono_tz::Europe::Berlin)),
+            },
+        ];
+
+        for test in TESTS.iter() {
+            // Get test values
+            let expected_file = test.expected_file;
+            let input_file = test.input_file;
+
+            let expected = fs::read_to_string(expected_file).unwrap();
+
+            // Get input arguments
+            let input = fs::read_to_string(input_file).unwrap();
+            let options = Options {
+                output_timezone: test.output_timezone,
+                ..Default::default()
+            };
+            let xmltv_listing = Reader::new(input.as_bytes()).read_listing().unwrap();
+
+            // Run test
+            let mut visitor = Csv::new(test.format, test.columns, &options);
+            let table = export::export::<Table>(&mut visitor, &xmltv_listing, &options).unwrap();
+
+            // Run test with the streaming XMLTV reader
+            let mut visitor = Csv::new(test.format, test.columns, &options);
+            let streamed_table =
+                export::export_from_reader::<Table>(&mut visitor, input.as_bytes(), &options)
+                    .unwrap();
+            assert_eq!(
+                streamed_table, table,
+                "for streamed input file {input_file}"
+            );
+
+            let mut output = vec![];
+            table.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+
+            // Check result
+            assert_eq!(output, expected, "for output file {expected_file}");
+        }
+    }
+
+    #[test]
+    fn test_episode() {
+        const TESTS: [(&str, &str, &str); 6] = [
+            ("xmltv_ns", "0 . 1 . 0/1", "S01E02"),
+            ("xmltv_ns", ". 11 .", "E12"),
+            ("xmltv_ns", "2..", "S03"),
+            ("xmltv_ns", " 4294967295.0. ", "4294967295.0."),
+            ("onscreen", " S4 E5 ", "S4 E5"),
+            ("dd_progid", "EP00000001.0001", "EP00000001.0001"),
+        ];
+        for (system, value, expected) in TESTS {
+            let programme = Programme {
+                episode_num: vec![EpisodeNum {
+                    system: system.to_string(),
+                    value: value.to_string(),
+                }],
+                ..Default::default()
+            };
+            assert_eq!(episode(&programme), expected, "for {system:?} {value:?}");
+        }
+    }
+}
@@ -0,0 +1,682 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, Timelike};
+use clap::ValueEnum;
+use quick_xml::escape::escape;
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use xmltv::{Programme, Tv};
+
+use crate::badge::{badges, title_with_badges, BadgePlacement};
+use crate::error::Error;
+use crate::export::{channel_name, Annotations, Options, Visitor};
+use crate::export::{DEFAULT_FEED_CHANNEL_DESCRIPTION, DEFAULT_FEED_CHANNEL_TITLE};
+use crate::input;
+use crate::xmltv::{find_name, find_value, parse_programme_times};
+
+/// Minutes per grid column.
+const HTML_SLOT_MINUTES: u32 = 5;
+
+/// Self-contained stylesheet of the HTML TV guide.
+const HTML_STYLESHEET: &str = "\
+body { font-family: sans-serif; margin: 1em; color: #222; background: #fff; }
+h1 { margin-bottom: 0; }
+.description { color: #666; margin-top: 0.25em; }
+.day { margin-top: 2em; break-before: page; }
+.guide { overflow-x: auto; }
+table.grid { border-collapse: collapse; table-layout: fixed; font-size: 0.85em; }
+table.grid th, table.grid td { border: 1px solid #ccc; padding: 0.2em 0.3em; vertical-align: top; overflow: hidden; }
+table.grid thead th { background: #eee; text-align: left; white-space: nowrap; }
+th.channel { background: #f6f6f6; text-align: left; white-space: nowrap; }
+.channel-icon { height: 1.5em; vertical-align: middle; margin-right: 0.3em; }
+td.empty { background: #fafafa; }
+.programme .time { display: block; color: #555; font-size: 0.9em; }
+.programme .title { font-weight: bold; }
+.programme .programme-description { margin: 0.2em 0 0; color: #444; }
+.programme.watchlist { outline: 2px solid #d33; outline-offset: -2px; }
+ul.programmes { list-style: none; padding-left: 0; }
+ul.programmes li { padding: 0.3em 0.5em; margin-bottom: 0.2em; border-left: 0.3em solid #ccc; }
+.category-news { background: #e3f0fb; border-left-color: #3a7bd5; }
+.category-sport, .category-sports { background: #e6f6e6; border-left-color: #3c9a3c; }
+.category-film, .category-movie, .category-movies { background: #fbe9e7; border-left-color: #d35400; }
+.category-series, .category-drama, .category-comedy { background: #f3e8fb; border-left-color: #8e44ad; }
+.category-children, .category-kids, .category-animation { background: #fff8d6; border-left-color: #e0b000; }
+.category-documentary, .category-nature, .category-history { background: #e8f6f3; border-left-color: #16a085; }
+.category-music, .category-entertainment { background: #fde8f1; border-left-color: #c2185b; }
+@media print { body { margin: 0; } .guide { overflow: visible; } }
+";
+
+/// View of the HTML TV guide.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum HtmlView {
+    /// A table per day, with a row per channel and time as columns
+    #[default]
+    Grid,
+
+    /// A list per day, with the programmes of each channel
+    List,
+}
+
+/// Exports an XMLTV TV listing to an HTML TV guide.
+pub fn export(
+    title: &str,
+    description: Option<&str>,
+    view: HtmlView,
+    options: &Options,
+    file: Option<&str>,
+) -> Result<Guide, Error> {
+    let (reader, _) = input::open(file.map(Path::new))?;
+
+    export_from_reader(title, description, view, options, reader)
+}
+
+/// Exports an XMLTV TV listing, read from a reader, to an HTML TV guide.
+pub fn export_from_reader(
+    title: &str,
+    description: Option<&str>,
+    view: HtmlView,
+    options: &Options,
+    reader: impl Read,
+) -> Result<Guide, Error> {
+    let mut visitor = Html::new(title, description, view, options);
+
+    super::export_from_reader::<Guide>(&mut visitor, BufReader::new(reader), options)
+}
+
+/// Exports an XMLTV TV listing string to an HTML TV guide.
+pub fn export_from_str(
+    title: &str,
+    description: Option<&str>,
+    view: HtmlView,
+    options: &Options,
+    xmltv_listing: &str,
+) -> Result<Guide, Error> {
+    export_from_reader(title, description, view, options, xmltv_listing.as_bytes())
+}
+
+/// Exports a parsed XMLTV TV listing to an HTML TV guide.
+pub fn export_from_tv(
+    title: &str,
+    description: Option<&str>,
+    view: HtmlView,
+    options: &Options,
+    xmltv_listing: &Tv,
+) -> Result<Guide, Error> {
+    let mut visitor = Html::new(title, description, view, options);
+
+    super::export::<Guide>(&mut visitor, xmltv_listing, options)
+}
+
+//
+
+/// HTML TV guide: a single HTML page with a self-contained stylesheet.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Guide {
+    pub title: String,
+    pub description: String,
+    pub view: HtmlView,
+    pub date_format: String,
+    pub time_format: String,
+
+    /// Channels, in channel order, followed by the channels of programmes without `<channel>` element
+    pub channels: Vec<GuideChannel>,
+
+    pub programmes: Vec<GuideProgramme>,
+}
+
+/// Channel of the HTML TV guide.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GuideChannel {
+    pub id: String,
+
+    /// Display name, or else the channel id
+    pub name: String,
+
+    pub icon: Option<String>,
+}
+
+/// Programme of the HTML TV guide.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GuideProgramme {
+    pub channel: String,
+
+    /// Start time, in the output timezone
+    pub start: DateTime<FixedOffset>,
+
+    /// Stop time, in the output timezone
+    pub stop: DateTime<FixedOffset>,
+
+    pub title: String,
+    pub description: String,
+    pub category: Option<String>,
+
+    /// Whether the programme matches a watchlist rule
+    pub watchlist: bool,
+}
+
+impl Guide {
+    /// Writes the HTML page.
+    pub fn write_to(&self, mut writer: impl Write) -> Result<(), Error> {
+        let title = escape(&self.title);
+        writeln!(writer, "<!DOCTYPE html>")?;
+        writeln!(writer, "<html>")?;
+        writeln!(writer, "<head>")?;
+        writeln!(writer, "<meta charset=\"utf-8\">")?;
+        writeln!(
+            writer,
+            "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">"
+        )?;
+        writeln!(writer, "<title>{title}</title>")?;
+        write!(writer, "<style>\n{HTML_STYLESHEET}</style>\n")?;
+        writeln!(writer, "</head>")?;
+        writeln!(writer, "<body>")?;
+        writeln!(writer, "<h1>{title}</h1>")?;
+        writeln!(
+            writer,
+            "<p class=\"description\">{}</p>",
+            escape(&self.description)
+        )?;
+
+        for (day, programmes) in self.days() {
+            writeln!(writer, "<section class=\"day\">")?;
+            let date = programmes[0].start.format(&self.date_format);
+            writeln!(writer, "<h2>{}</h2>", escape(&date.to_string()))?;
+            match self.view {
+                HtmlView::Grid => self.write_grid(&mut writer, day, &programmes)?,
+                HtmlView::List => self.write_list(&mut writer, &programmes)?,
+            }
+            writeln!(writer, "</section>")?;
+        }
+
+        writeln!(writer, "</body>")?;
+        writeln!(writer, "</html>")?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Returns the programmes by start day, in start time order.
+    fn days(&self) -> BTreeMap<NaiveDate, Vec<&GuideProgramme>> {
+        let mut days: BTreeMap<NaiveDate, Vec<&GuideProgramme>> = BTreeMap::new();
+        for programme in &self.programmes {
+            days.entry(programme.start.date_naive())
+                .or_default()
+                .push(programme);
+        }
+        for programmes in days.values_mut() {
+            programmes.sort_by_key(|programme| programme.start);
+        }
+
+        days
+    }
+
+    /// Returns the channels with programmes, in channel order.
+    fn channels<'a>(&'a self, programmes: &[&GuideProgramme]) -> Vec<&'a GuideChannel> {
+        self.channels
+            .iter()
+            .filter(|channel| {
+                programmes
+                    .iter()
+                    .any(|programme| programme.channel == channel.id)
+            })
+            .collect()
+    }
+
+    /// Writes a table with a row per channel, and a column per 5 minutes of the hours of the day
+    /// with programmes. A programme is clipped to the end of its start day.
+    fn write_grid(
+        &self,
+        writer: &mut impl Write,
+        day: NaiveDate,
+        programmes: &[&GuideProgramme],
+    ) -> Result<(), Error> {
+        let slot = |datetime: DateTime<FixedOffset>| {
+            if datetime.date_naive() > day {
+                24 * 60 / HTML_SLOT_MINUTES
+            } else {
+                (datetime.hour() * 60 + datetime.minute()) / HTML_SLOT_MINUTES
+            }
+        };
+        let slots_per_hour = 60 / HTML_SLOT_MINUTES;
+
+        let first_hour = programmes
+            .iter()
+            .map(|programme| programme.start.hour())
+            .min()
+            .unwrap_or_default();
+        let last_hour = programmes
+            .iter()
+            .map(|programme| slot(programme.stop).div_ceil(slots_per_hour))
+            .max()
+            .unwrap_or_default()
+            .clamp(first_hour + 1, 24);
+        let (first_slot, last_slot) = (first_hour * slots_per_hour, last_hour * slots_per_hour);
+
+        writeln!(writer, "<div class=\"guide\">")?;
+        writeln!(writer, "<table class=\"grid\">")?;
+
+        // Header with the hours, and a row of slot cells that sets the column widths
+        write!(writer, "<thead><tr><th class=\"channel\"></th>")?;
+        let offset = *programmes[0].start.offset();
+        for hour in first_hour..last_hour {
+            let time = day
+                .and_hms_opt(hour, 0, 0)
+                .and_then(|datetime| datetime.and_local_timezone(offset).single())
+                .map(|datetime| datetime.format(&self.time_format).to_string())
+                .unwrap_or_default();
+            write!(
+                writer,
+                "<th colspan=\"{slots_per_hour}\">{}</th>",
+                escape(&time)
+            )?;
+        }
+        writeln!(writer, "</tr></thead>")?;
+        writeln!(writer, "<tbody>")?;
+
+        for channel in self.channels(programmes) {
+            write!(writer, "<tr>")?;
+            write_channel(writer, "th", channel)?;
+
+            let mut cursor = first_slot;
+            for programme in programmes
+                .iter()
+                .filter(|programme| programme.channel == channel.id)
+            {
+                let start = slot(programme.start).max(cursor);
+                let stop = slot(programme.stop)
+                    .max(slot(programme.start) + 1)
+                    .min(last_slot);
+                if stop <= start {
+                    continue;
+                }
+                if start > cursor {
+                    write!(
+                        writer,
+                        "<td class=\"empty\" colspan=\"{}\"></td>",
+                        start - cursor
+                    )?;
+                }
+                write!(writer, "<td colspan=\"{}\"", stop - start)?;
+                self.write_programme(writer, "td", programme, false)?;
+                cursor = stop;
+            }
+            if cursor < last_slot {
+                write!(
+                    writer,
+                    "<td class=\"empty\" colspan=\"{}\"></td>",
+                    last_slot - cursor
+                )?;
+            }
+            writeln!(writer, "</tr>")?;
+        }
+
+        writeln!(writer, "</tbody>")?;
+        writeln!(writer, "</table>")?;
+        writeln!(writer, "</div>")?;
+
+        Ok(())
+    }
+
+    /// Writes a heading and a list of programmes per channel.
+    fn write_list(
+        &self,
+        writer: &mut impl Write,
+        programmes: &[&GuideProgramme],
+    ) -> Result<(), Error> {
+        for channel in self.channels(programmes) {
+            write_channel(writer, "h3", channel)?;
+            writeln!(writer)?;
+            writeln!(writer, "<ul class=\"programmes\">")?;
+            for programme in programmes
+                .iter()
+                .filter(|programme| programme.channel == channel.id)
+            {
+                write!(writer, "<li")?;
+                self.write_programme(writer, "li", programme, true)?;
+                writeln!(writer)?;
+            }
+            writeln!(writer, "</ul>")?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the rest of the programme element, after the start of its opening tag.
+    fn write_programme(
+        &self,
+        writer: &mut impl Write,
+        element: &str,
+        programme: &GuideProgramme,
+        with_description: bool,
+    ) -> Result<(), Error> {
+        let mut class = "programme".to_string();
+        if let Some(category) = programme.category.as_deref().and_then(category_class) {
+            class.push(' ');
+            class.push_str(&category);
+        }
+        if programme.watchlist {
+            class.push_str(" watchlist");
+        }
+        write!(writer, " class=\"{class}\"")?;
+        if !with_description && !programme.description.is_empty() {
+            write!(writer, " title=\"{}\"", escape(&programme.description))?;
+        }
+
+        let time = format!(
+            "{} - {}",
+            programme.start.format(&self.time_format),
+            programme.stop.format(&self.time_format)
+        );
+        write!(
+            writer,
+            "><span class=\"time\">{}</span><span class=\"title\">{}</span>",
+            escape(&time),
+            escape(&programme.title)
+        )?;
+        if with_description && !programme.description.is_empty() {
+            write!(
+                writer,
+                "<p class=\"programme-description\">{}</p>",
+                escape(&programme.description)
+            )?;
+        }
+        write!(writer, "</{element}>")?;
+
+        Ok(())
+    }
+}
+
+/// Writes the channel icon and name in an element.
+fn write_channel(
+    writer: &mut impl Write,
+    element: &str,
+    channel: &GuideChannel,
+) -> Result<(), Error> {
+    write!(writer, "<{element} class=\"channel\">")?;
+    if let Some(icon) = &channel.icon {
+        write!(
+            writer,
+            "<img class=\"channel-icon\" src=\"{}\" alt=\"\">",
+            escape(icon)
+        )?;
+    }
+    write!(writer, "{}</{element}>", escape(&channel.name))?;
+
+    Ok(())
+}
+
+/// Returns the CSS class of a category: "category-" followed by the lowercase category name,
+/// with other characters than ASCII letters and digits replaced by '-'. Example: "category-news".
+fn category_class(category: &str) -> Option<String> {
+    let name = category
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+
+    if !name.is_empty() {
+        Some(format!("category-{name}"))
+    } else {
+        None
+    }
+}
+
+//
+
+/// HTML TV guide export struct.
+pub(crate) struct Html<'a> {
+    title: &'a str,
+    description: Option<&'a str>,
+    view: HtmlView,
+    options: &'a Options<'a>,
+
+    // Visitor state
+    guide: Guide,
+}
+
+impl<'a> Html<'a> {
+    pub fn new(
+        title: &'a str,
+        description: Option<&'a str>,
+        view: HtmlView,
+        options: &'a Options,
+    ) -> Self {
+        let title = if !title.is_empty() {
+            title
+        } else {
+            DEFAULT_FEED_CHANNEL_TITLE
+        };
+
+        Self {
+            title,
+            description,
+            view,
+            options,
+
+            // Visitor state
+            guide: Guide::default(),
+        }
+    }
+}
+
+impl Visitor for Html<'_> {
+    type Output = Guide;
+
+    /// Exports from XMLTV TV listing to HTML TV guide.
+    fn visit_tv(&mut self, _xmltv_listing: &Tv) -> Result<(), Error> {
+        self.guide.title = self.title.to_string();
+        self.guide.description = self
+            .description
+            .unwrap_or(DEFAULT_FEED_CHANNEL_DESCRIPTION)
+            .to_string();
+        self.guide.view = self.view;
+        self.guide.date_format = self.options.date_format.to_string();
+        self.guide.time_format = self.options.time_format.to_string();
+
+        Ok(())
+    }
+
+    fn visit_channels_start(&mut self) -> Result<(), Error> {
+        self.guide.channels.clear();
+
+        Ok(())
+    }
+
+    /// Exports from XMLTV channel to HTML TV guide channel.
+    fn visit_channel(&mut self, xmltv_channel: &xmltv::Channel) -> Result<(), Error> {
+        let language = self.options.language.filter(|l| !l.is_empty());
+
+        let name = channel_name(xmltv_channel, language);
+        let icon = xmltv_channel
+            .icons
+            .iter()
+            .map(|icon| icon.trim())
+            .find(|icon| !icon.is_empty());
+
+        self.guide.channels.push(GuideChannel {
+            id: xmltv_channel.id.clone(),
+            name: name.to_string(),
+            icon: icon.map(str::to_string),
+        });
+
+        Ok(())
+    }
+
+    fn visit_programmes_start(&mut self) -> Result<(), Error> {
+        self.guide.programmes.clear();
+
+        Ok(())
+    }
+
+    /// Exports from XMLTV programme to HTML TV guide programme.
+    fn visit_programme(&mut self, xmltv_programme: &Programme) -> Result<(), Error> {
+        self.visit_annotated_programme(xmltv_programme, &Annotations::default())
+    }
+
+    /// Exports from XMLTV programme to HTML TV guide programme, highlighted if it matches a
+    /// watchlist rule.
+    fn visit_annotated_programme(
+        &mut self,
+        xmltv_programme: &Programme,
+        annotations: &Annotations,
+    ) -> Result<(), Error> {
+        let language = self.options.language.filter(|l| !l.is_empty());
+
+        let channel_id = &xmltv_programme.channel;
+        if !self
+            .guide
+            .channels
+            .iter()
+            .any(|channel| channel.id == *channel_id)
+        {
+            self.guide.channels.push(GuideChannel {
+                id: channel_id.clone(),
+                name: channel_id.clone(),
+                icon: None,
+            });
+        }
+
+        let (starttime_dt, stoptime_dt) = parse_programme_times(xmltv_programme, self.options)?;
+        let starttime_dt = self.options.to_output_timezone(starttime_dt);
+        let stoptime_dt = self.options.to_output_timezone(stoptime_dt);
+
+        //
+
+        let title = find_value(&xmltv_programme.titles, language);
+        let title = match self.options.badges {
+            Some(BadgePlacement::Title) => {
+                title_with_badges(title, &badges(xmltv_programme, self.options))
+            }
+            _ => title.to_string(),
+        };
+
+        let description = find_value(&xmltv_programme.descriptions, language)
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let category = find_name(&xmltv_programme.categories, language).trim();
+
+        self.guide.programmes.push(GuideProgramme {
+            channel: channel_id.clone(),
+            start: starttime_dt,
+            stop: stoptime_dt,
+            title,
+            description,
+            category: Some(category)
+                .filter(|category| !category.is_empty())
+                .map(str::to_string),
+            watchlist: !annotations.rules.is_empty(),
+        });
+
+        Ok(())
+    }
+
+    /// Returns the exported HTML TV guide.
+    fn result(&self) -> Result<Self::Output, Error> {
+        Ok(self.guide.clone())
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    use super::*;
+    use crate::export::{self, Timezone};
+    use crate::xmltv::reader::Reader;
+
+    #[derive(Default)]
+    struct Test<'a> {
+        input_file: &'a str,
+        expected_file: &'a str,
+        view: HtmlView,
+        output_timezone: Option<Timezone>,
+    }
+
+    #[test]
+    fn test() {
+        const TESTS: [Test; 4] = [
+            Test {
+                input_file: "tests/input/simple.xml",
+                expected_file: "tests/output/html/simple.html",
+                view: HtmlView::Grid,
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/html/timezones.html",
+                view: HtmlView::Grid,
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/html/timezones-list.html",
+                view: HtmlView::List,
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/html/timezones-output-timezone.html",
+                view: HtmlView::Grid,
+                output_timezone: Some(Timezone::Iana(chrono_tz::Europe::Berlin)),
+            },
+        ];
+
+        for test in TESTS.iter() {
+            // Get test values
+            let expected_file = test.expected_file;
+            let input_file = test.input_file;
+
+            let expected = fs::read_to_string(expected_file).unwrap();
+
+            // Get input arguments
+            let input = fs::read_to_string(input_file).unwrap();
+            let options = Options {
+                output_timezone: test.output_timezone,
+                ..Default::default()
+            };
+            let xmltv_listing = Reader::new(input.as_bytes()).read_listing().unwrap();
+
+            // Run test
+            let mut visitor = Html::new(DEFAULT_FEED_CHANNEL_TITLE, None, test.view, &options);
+            let guide = export::export::<Guide>(&mut visitor, &xmltv_listing, &options).unwrap();
+
+            // Run test with the streaming XMLTV reader
+            let mut visitor = Html::new(DEFAULT_FEED_CHANNEL_TITLE, None, test.view, &options);
+            let streamed_guide =
+                export::export_from_reader::<Guide>(&mut visitor, input.as_bytes(), &options)
+                    .unwrap();
+            assert_eq!(
+                streamed_guide, guide,
+                "for streamed input file {input_file}"
+            );
+
+            let mut output = vec![];
+            guide.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+
+            // Check result
+            assert_eq!(output, expected, "for output file {expected_file}");
+        }
+    }
+
+    #[test]
+    fn test_category_class() {
+        const TESTS: [(&str, Option<&str>); 3] = [
+            ("News", Some("category-news")),
+            ("Sci-Fi & Fantasy", Some("category-sci-fi-fantasy")),
+            (" / ", None),
+        ];
+        for (category, expected) in TESTS {
+            assert_eq!(
+                category_class(category).as_deref(),
+                expected,
+                "for {category:?}"
+            );
+        }
+    }
+}
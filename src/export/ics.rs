@@ -0,0 +1,601 @@
+use chrono::{DateTime, FixedOffset, Offset, TimeDelta, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz, TzOffset};
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use xmltv::{Programme, Tv};
+
+use crate::badge::{badges, title_with_badges, BadgePlacement};
+use crate::error::Error;
+use crate::export::{find_channel_name, Annotations, Options, Timezone, Visitor};
+use crate::export::{
+    DEFAULT_FEED_CHANNEL_DESCRIPTION, DEFAULT_FEED_CHANNEL_TITLE, GUID_DATETIME_FORMAT,
+};
+use crate::input;
+use crate::xmltv::{find_value, first_url, parse_programme_times};
+
+pub const ICS_PRODUCT_ID: &str = "-//xmltv2rss//NONSGML xmltv2rss//EN";
+
+/// Domain part of the event UIDs.
+const ICS_UID_DOMAIN: &str = "xmltv2rss";
+
+/// iCalendar UTC date and time format.
+const ICS_DATETIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// iCalendar local date and time format, of a date and time with a timezone id.
+const ICS_LOCAL_DATETIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+/// Maximum length of a content line in octets, without the line break.
+const ICS_LINE_LENGTH: usize = 75;
+
+/// Exports an XMLTV TV listing to an iCalendar calendar.
+pub fn export(
+    title: &str,
+    description: Option<&str>,
+    options: &Options,
+    file: Option<&str>,
+) -> Result<Calendar, Error> {
+    let (reader, _) = input::open(file.map(Path::new))?;
+
+    export_from_reader(title, description, options, reader)
+}
+
+/// Exports an XMLTV TV listing, read from a reader, to an iCalendar calendar.
+pub fn export_from_reader(
+    title: &str,
+    description: Option<&str>,
+    options: &Options,
+    reader: impl Read,
+) -> Result<Calendar, Error> {
+    let mut visitor = Ics::new(title, description, options);
+
+    super::export_from_reader::<Calendar>(&mut visitor, BufReader::new(reader), options)
+}
+
+/// Exports an XMLTV TV listing string to an iCalendar calendar.
+pub fn export_from_str(
+    title: &str,
+    description: Option<&str>,
+    options: &Options,
+    xmltv_listing: &str,
+) -> Result<Calendar, Error> {
+    export_from_reader(title, description, options, xmltv_listing.as_bytes())
+}
+
+/// Exports a parsed XMLTV TV listing to an iCalendar calendar.
+pub fn export_from_tv(
+    title: &str,
+    description: Option<&str>,
+    options: &Options,
+    xmltv_listing: &Tv,
+) -> Result<Calendar, Error> {
+    let mut visitor = Ics::new(title, description, options);
+
+    super::export::<Calendar>(&mut visitor, xmltv_listing, options)
+}
+
+//
+
+/// iCalendar calendar, with an event per programme. See RFC 5545.
+///
+/// The event times are written in UTC. With an IANA output timezone, they are written as local times
+/// in that timezone instead, with a `VTIMEZONE` component that covers the event times, and the timezone
+/// is the `X-WR-TIMEZONE` display timezone of the calendar.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Calendar {
+    pub name: String,
+    pub description: String,
+
+    /// Timezone of the event times, if the output timezone is an IANA timezone
+    pub timezone: Option<Tz>,
+
+    pub events: Vec<CalendarEvent>,
+}
+
+/// iCalendar `VEVENT`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CalendarEvent {
+    /// Unique id, stable across regenerations of the calendar
+    pub uid: String,
+
+    /// Time the calendar was generated
+    pub dtstamp: DateTime<Utc>,
+
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub summary: String,
+    pub description: Option<String>,
+
+    /// Channel display name, or else the channel id
+    pub location: String,
+
+    pub categories: Vec<String>,
+    pub url: Option<String>,
+}
+
+impl Calendar {
+    /// Writes the calendar, with CRLF line breaks and folded content lines.
+    pub fn write_to(&self, mut writer: impl Write) -> Result<(), Error> {
+        write_line(&mut writer, "BEGIN:VCALENDAR")?;
+        write_line(&mut writer, "VERSION:2.0")?;
+        write_line(&mut writer, &format!("PRODID:{ICS_PRODUCT_ID}"))?;
+        write_line(&mut writer, "CALSCALE:GREGORIAN")?;
+        write_line(&mut writer, "METHOD:PUBLISH")?;
+        write_line(&mut writer, &format!("X-WR-CALNAME:{}", escape(&self.name)))?;
+        write_line(
+            &mut writer,
+            &format!("X-WR-CALDESC:{}", escape(&self.description)),
+        )?;
+        if let Some(timezone) = self.timezone {
+            write_line(
+                &mut writer,
+                &format!("X-WR-TIMEZONE:{}", escape(timezone.name())),
+            )?;
+            self.write_timezone_to(&mut writer, timezone)?;
+        }
+
+        for event in &self.events {
+            event.write_to(&mut writer, self.timezone)?;
+        }
+
+        write_line(&mut writer, "END:VCALENDAR")?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the `VTIMEZONE` component of the timezone, with an observance from the first event time
+    /// and an observance for each change of the UTC offset or timezone name until the last event time.
+    fn write_timezone_to(&self, writer: &mut impl Write, timezone: Tz) -> Result<(), Error> {
+        let times = self
+            .events
+            .iter()
+            .flat_map(|event| [event.start, event.end]);
+        let (Some(first), Some(last)) = (times.clone().min(), times.max()) else {
+            return Ok(());
+        };
+
+        write_line(writer, "BEGIN:VTIMEZONE")?;
+        write_line(writer, &format!("TZID:{}", timezone.name()))?;
+        let mut offset = timezone.offset_from_utc_datetime(&first.naive_utc());
+        write_observance(writer, first, offset, offset)?;
+        for transition in offset_transitions(timezone, first, last) {
+            let next_offset = timezone.offset_from_utc_datetime(&transition.naive_utc());
+            write_observance(writer, transition, offset, next_offset)?;
+            offset = next_offset;
+        }
+        write_line(writer, "END:VTIMEZONE")?;
+
+        Ok(())
+    }
+}
+
+impl CalendarEvent {
+    fn write_to(&self, writer: &mut impl Write, timezone: Option<Tz>) -> Result<(), Error> {
+        write_line(writer, "BEGIN:VEVENT")?;
+        write_line(writer, &format!("UID:{}", escape(&self.uid)))?;
+        write_line(
+            writer,
+            &format!("DTSTAMP:{}", self.dtstamp.format(ICS_DATETIME_FORMAT)),
+        )?;
+        write_line(writer, &datetime_property("DTSTART", self.start, timezone))?;
+        write_line(writer, &datetime_property("DTEND", self.end, timezone))?;
+        write_line(writer, &format!("SUMMARY:{}", escape(&self.summary)))?;
+        if let Some(description) = &self.description {
+            write_line(writer, &format!("DESCRIPTION:{}", escape(description)))?;
+        }
+        write_line(writer, &format!("LOCATION:{}", escape(&self.location)))?;
+        if !self.categories.is_empty() {
+            let categories: Vec<_> = self
+                .categories
+                .iter()
+                .map(|category| escape(category))
+                .collect();
+            write_line(writer, &format!("CATEGORIES:{}", categories.join(",")))?;
+        }
+        if let Some(url) = &self.url {
+            write_line(writer, &format!("URL:{url}"))?;
+        }
+        write_line(writer, "END:VEVENT")?;
+
+        Ok(())
+    }
+}
+
+/// Returns a date and time property, in UTC or as a local time with the timezone id.
+fn datetime_property(name: &str, datetime: DateTime<Utc>, timezone: Option<Tz>) -> String {
+    match timezone {
+        Some(timezone) => format!(
+            "{name};TZID={}:{}",
+            timezone.name(),
+            datetime
+                .with_timezone(&timezone)
+                .format(ICS_LOCAL_DATETIME_FORMAT)
+        ),
+        None => format!("{name}:{}", datetime.format(ICS_DATETIME_FORMAT)),
+    }
+}
+
+/// Writes a `STANDARD` or `DAYLIGHT` observance of a `VTIMEZONE` component, that starts at the time.
+fn write_observance(
+    writer: &mut impl Write,
+    start: DateTime<Utc>,
+    offset_from: TzOffset,
+    offset_to: TzOffset,
+) -> Result<(), Error> {
+    let component = if offset_to.dst_offset().is_zero() {
+        "STANDARD"
+    } else {
+        "DAYLIGHT"
+    };
+    let start = start.with_timezone(&offset_from.fix());
+
+    write_line(writer, &format!("BEGIN:{component}"))?;
+    write_line(
+        writer,
+        &format!("DTSTART:{}", start.format(ICS_LOCAL_DATETIME_FORMAT)),
+    )?;
+    write_line(
+        writer,
+        &format!("TZOFFSETFROM:{}", utc_offset(offset_from.fix())),
+    )?;
+    write_line(
+        writer,
+        &format!("TZOFFSETTO:{}", utc_offset(offset_to.fix())),
+    )?;
+    if let Some(name) = offset_to.abbreviation() {
+        write_line(writer, &format!("TZNAME:{}", escape(name)))?;
+    }
+    write_line(writer, &format!("END:{component}"))?;
+
+    Ok(())
+}
+
+/// Returns the times, after the start time until the end time, that the UTC offset or the name
+/// of the timezone changes. Assumes at most one change per day.
+fn offset_transitions(
+    timezone: Tz,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Vec<DateTime<Utc>> {
+    let offset = |time: DateTime<Utc>| timezone.offset_from_utc_datetime(&time.naive_utc());
+
+    let mut transitions = vec![];
+    let mut time = start;
+    while time < end {
+        let next = (time + TimeDelta::days(1)).min(end);
+        if offset(next) != offset(time) {
+            // Bisect to the first second with the next offset
+            let (mut before, mut after) = (time, next);
+            while after - before > TimeDelta::seconds(1) {
+                let middle = before + TimeDelta::seconds((after - before).num_seconds() / 2);
+                if offset(middle) == offset(before) {
+                    before = middle;
+                } else {
+                    after = middle;
+                }
+            }
+            transitions.push(after);
+        }
+        time = next;
+    }
+
+    transitions
+}
+
+/// Returns the UTC offset as "+hhmm", or as "+hhmmss" if it has seconds.
+fn utc_offset(offset: FixedOffset) -> String {
+    let seconds = offset.local_minus_utc();
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.unsigned_abs();
+
+    let mut utc_offset = format!("{sign}{:02}{:02}", seconds / 3600, seconds / 60 % 60);
+    if !seconds.is_multiple_of(60) {
+        utc_offset.push_str(&format!("{:02}", seconds % 60));
+    }
+
+    utc_offset
+}
+
+/// Escapes an iCalendar text value: backslashes, semicolons, commas and line breaks.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Writes a content line, folded into lines of at most 75 octets, without splitting characters.
+/// Continuation lines start with a space.
+fn write_line(writer: &mut impl Write, line: &str) -> Result<(), Error> {
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > ICS_LINE_LENGTH {
+            writer.write_all(b"\r\n ")?;
+            length = 1;
+        }
+        let mut buffer = [0; 4];
+        writer.write_all(c.encode_utf8(&mut buffer).as_bytes())?;
+        length += c.len_utf8();
+    }
+    writer.write_all(b"\r\n")?;
+
+    Ok(())
+}
+
+//
+
+/// iCalendar export struct.
+pub(crate) struct Ics<'a> {
+    title: &'a str,
+    description: Option<&'a str>,
+    options: &'a Options<'a>,
+
+    // Visitor state
+    xmltv_channels: Vec<xmltv::Channel>,
+    calendar: Calendar,
+}
+
+impl<'a> Ics<'a> {
+    pub fn new(title: &'a str, description: Option<&'a str>, options: &'a Options) -> Self {
+        let title = if !title.is_empty() {
+            title
+        } else {
+            DEFAULT_FEED_CHANNEL_TITLE
+        };
+
+        Self {
+            title,
+            description,
+            options,
+
+            // Visitor state
+            xmltv_channels: vec![],
+            calendar: Calendar::default(),
+        }
+    }
+}
+
+impl Visitor for Ics<'_> {
+    type Output = Calendar;
+
+    /// Exports from XMLTV TV listing to iCalendar calendar.
+    fn visit_tv(&mut self, _xmltv_listing: &Tv) -> Result<(), Error> {
+        self.calendar.name = self.title.to_string();
+        self.calendar.description = self
+            .description
+            .unwrap_or(DEFAULT_FEED_CHANNEL_DESCRIPTION)
+            .to_string();
+        self.calendar.timezone = match self.options.output_timezone {
+            Some(Timezone::Iana(timezone)) => Some(timezone),
+            _ => None,
+        };
+
+        Ok(())
+    }
+
+    fn visit_channels_start(&mut self) -> Result<(), Error> {
+        self.xmltv_channels.clear();
+
+        Ok(())
+    }
+
+    fn visit_channel(&mut self, xmltv_channel: &xmltv::Channel) -> Result<(), Error> {
+        self.xmltv_channels.push(xmltv_channel.clone());
+
+        Ok(())
+    }
+
+    fn visit_programmes_start(&mut self) -> Result<(), Error> {
+        self.calendar.events.clear();
+
+        Ok(())
+    }
+
+    /// Exports from XMLTV programme to iCalendar event.
+    fn visit_programme(&mut self, xmltv_programme: &Programme) -> Result<(), Error> {
+        self.visit_annotated_programme(xmltv_programme, &Annotations::default())
+    }
+
+    /// Exports from XMLTV programme to iCalendar event, with a category for each matching
+    /// watchlist rule.
+    fn visit_annotated_programme(
+        &mut self,
+        xmltv_programme: &Programme,
+        annotations: &Annotations,
+    ) -> Result<(), Error> {
+        let language = self.options.language.filter(|l| !l.is_empty());
+
+        let channel_id = &xmltv_programme.channel;
+
+        let (starttime_dt, stoptime_dt) = parse_programme_times(xmltv_programme, self.options)?;
+
+        //
+
+        let title = find_value(&xmltv_programme.titles, language);
+        let summary = match self.options.badges {
+            Some(BadgePlacement::Title) => {
+                title_with_badges(title, &badges(xmltv_programme, self.options))
+            }
+            _ => title.to_string(),
+        };
+
+        let description = find_value(&xmltv_programme.descriptions, language)
+            .trim()
+            .lines()
+            .map(|line| line.trim())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut categories: Vec<String> = vec![];
+        let programme_categories = xmltv_programme
+            .categories
+            .iter()
+            .filter(|category| language.is_none() || category.lang.as_deref() == language)
+            .map(|category| category.name.trim());
+        for category in programme_categories.chain(annotations.rules.iter().copied()) {
+            if !category.is_empty() && !categories.iter().any(|c| c == category) {
+                categories.push(category.to_string());
+            }
+        }
+
+        let event = CalendarEvent {
+            uid: format!(
+                "{channel_id}-{}@{ICS_UID_DOMAIN}",
+                starttime_dt.to_utc().format(GUID_DATETIME_FORMAT)
+            ),
+            dtstamp: self.options.now().to_utc(),
+            start: starttime_dt.to_utc(),
+            end: stoptime_dt.to_utc(),
+            summary,
+            description: Some(description).filter(|description| !description.is_empty()),
+            location: find_channel_name(&self.xmltv_channels, language, channel_id).to_string(),
+            categories,
+            url: first_url(&xmltv_programme.urls),
+        };
+
+        self.calendar.events.push(event);
+
+        Ok(())
+    }
+
+    /// Returns the exported iCalendar calendar.
+    fn result(&self) -> Result<Self::Output, Error> {
+        Ok(self.calendar.clone())
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Tz;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    use super::*;
+    use crate::export;
+    use crate::xmltv::reader::Reader;
+
+    #[derive(Default)]
+    struct Test<'a> {
+        input_file: &'a str,
+        expected_file: &'a str,
+        language: Option<&'a str>,
+        input_timezone: Option<Tz>,
+        output_timezone: Option<Timezone>,
+    }
+
+    #[test]
+    fn test() {
+        const TESTS: [Test; 5] = [
+            Test {
+                input_file: "tests/input/simple.xml",
+                expected_file: "tests/output/ics/simple.ics",
+                language: None,
+                input_timezone: None,
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/simple.xml",
+                expected_file: "tests/output/ics/simple-language.ics",
+                language: Some("fr-FR"),
+                input_timezone: None,
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/ics/timezones.ics",
+                language: None,
+                input_timezone: None,
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/ics/timezones-input-timezone.ics",
+                language: None,
+                input_timezone: Some(chrono_tz::Europe::Amsterdam),
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/ics/timezones-output-timezone.ics",
+                language: None,
+                input_timezone: None,
+                output_timezone: Some(Timezone::Iana(chrono_tz::Europe::Berlin)),
+            },
+        ];
+        let now = DateTime::parse_from_rfc3339("2024-01-01T12:00:00+00:00").unwrap();
+
+        for test in TESTS.iter() {
+            // Get test values
+            let expected_file = test.expected_file;
+            let input_file = test.input_file;
+
+            let expected = fs::read_to_string(expected_file).unwrap();
+
+            // Get input arguments
+            let input = fs::read_to_string(input_file).unwrap();
+            let options = Options {
+                language: test.language,
+                input_timezone: test.input_timezone,
+                output_timezone: test.output_timezone,
+                now: Some(now),
+                ..Default::default()
+            };
+            let xmltv_listing = Reader::new(input.as_bytes()).read_listing().unwrap();
+
+            // Run test
+            let mut visitor = Ics::new(DEFAULT_FEED_CHANNEL_TITLE, None, &options);
+            let calendar =
+                export::export::<Calendar>(&mut visitor, &xmltv_listing, &options).unwrap();
+
+            // Run test with the streaming XMLTV reader
+            let mut visitor = Ics::new(DEFAULT_FEED_CHANNEL_TITLE, None, &options);
+            let streamed_calendar =
+                export::export_from_reader::<Calendar>(&mut visitor, input.as_bytes(), &options)
+                    .unwrap();
+            assert_eq!(
+                streamed_calendar, calendar,
+                "for streamed input file {input_file}"
+            );
+
+            let mut output = vec![];
+            calendar.write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+
+            // Check result
+            assert_eq!(output, expected, "for output file {expected_file}");
+        }
+    }
+
+    #[test]
+    fn test_write_line() {
+        const TESTS: [(&str, &str); 3] = [
+            ("SUMMARY:News", "SUMMARY:News\r\n"),
+            (
+                "DESCRIPTION:0123456789012345678901234567890123456789012345678901234567890123",
+                "DESCRIPTION:012345678901234567890123456789012345678901234567890123456789012\r\n 3\r\n",
+            ),
+            (
+                "SUMMARY:ééééééééééééééééééééééééééééééééééé",
+                "SUMMARY:ééééééééééééééééééééééééééééééééé\r\n éé\r\n",
+            ),
+        ];
+        for (line, expected) in TESTS {
+            let mut output = vec![];
+            write_line(&mut output, line).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), expected, "for {line:?}");
+        }
+
+        assert_eq!(escape("a,b;c\\d\r\ne"), "a\\,b\\;c\\\\d\\ne");
+    }
+}
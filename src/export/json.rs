@@ -0,0 +1,412 @@
+use chrono::SecondsFormat;
+use serde::Serialize;
+use serde_json::ser::PrettyFormatter;
+use serde_json::Serializer;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use xmltv::{Programme, Tv};
+
+use crate::badge::{badges, title_with_badges, BadgePlacement};
+use crate::error::Error;
+use crate::export::{item_description, Annotations, Options, Visitor, GUID_DATETIME_FORMAT};
+use crate::export::{DEFAULT_FEED_CHANNEL_DESCRIPTION, DEFAULT_FEED_CHANNEL_TITLE};
+use crate::input;
+use crate::xmltv::{find_value, first_url, parse_programme_times};
+
+pub const JSON_FEED_VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+/// Exports an XMLTV TV listing to a JSON feed.
+pub fn export(
+    title: &str,
+    link: &str,
+    description: Option<&str>,
+    options: &Options,
+    file: Option<&str>,
+) -> Result<JsonFeed, Error> {
+    let (reader, _) = input::open(file.map(Path::new))?;
+
+    export_from_reader(title, link, description, options, reader)
+}
+
+/// Exports an XMLTV TV listing, read from a reader, to a JSON feed.
+pub fn export_from_reader(
+    title: &str,
+    link: &str,
+    description: Option<&str>,
+    options: &Options,
+    reader: impl Read,
+) -> Result<JsonFeed, Error> {
+    let mut visitor = Json::new(title, link, description, options);
+
+    super::export_from_reader::<JsonFeed>(&mut visitor, BufReader::new(reader), options)
+}
+
+/// Exports an XMLTV TV listing string to a JSON feed.
+pub fn export_from_str(
+    title: &str,
+    link: &str,
+    description: Option<&str>,
+    options: &Options,
+    xmltv_listing: &str,
+) -> Result<JsonFeed, Error> {
+    export_from_reader(title, link, description, options, xmltv_listing.as_bytes())
+}
+
+/// Exports a parsed XMLTV TV listing to a JSON feed.
+pub fn export_from_tv(
+    title: &str,
+    link: &str,
+    description: Option<&str>,
+    options: &Options,
+    xmltv_listing: &Tv,
+) -> Result<JsonFeed, Error> {
+    let mut visitor = Json::new(title, link, description, options);
+
+    super::export::<JsonFeed>(&mut visitor, xmltv_listing, options)
+}
+
+//
+
+/// JSON Feed 1.1 feed. See <https://www.jsonfeed.org/version/1.1/>.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct JsonFeed {
+    pub version: String,
+    pub title: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub home_page_url: Option<String>,
+
+    pub description: String,
+
+    /// Icon of the XMLTV channel, if there is one channel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    pub items: Vec<JsonItem>,
+}
+
+/// JSON Feed 1.1 item.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct JsonItem {
+    pub id: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    pub title: String,
+    pub content_html: String,
+
+    /// Programme description
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+
+    /// First programme icon
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+
+    /// Programme start time, in RFC 3339 format
+    pub date_published: String,
+
+    /// Programme categories and matching watchlist rules
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl JsonFeed {
+    /// Writes the JSON feed, indented with the number of spaces.
+    pub fn write_to(&self, writer: impl Write, indent: usize) -> Result<(), Error> {
+        if indent > 0 {
+            let indent = vec![b' '; indent];
+            let formatter = PrettyFormatter::with_indent(&indent);
+            let mut serializer = Serializer::with_formatter(writer, formatter);
+            self.serialize(&mut serializer)?;
+        } else {
+            serde_json::to_writer(writer, self)?;
+        }
+
+        Ok(())
+    }
+}
+
+//
+
+/// JSON feed export struct.
+pub(crate) struct Json<'a> {
+    title: &'a str,
+    link: &'a str,
+    description: Option<&'a str>,
+    options: &'a Options<'a>,
+
+    // Visitor state
+    xmltv_channels: Vec<xmltv::Channel>,
+    feed: JsonFeed,
+}
+
+impl<'a> Json<'a> {
+    pub fn new(
+        title: &'a str,
+        link: &'a str,
+        description: Option<&'a str>,
+        options: &'a Options,
+    ) -> Self {
+        let title = if !title.is_empty() {
+            title
+        } else {
+            DEFAULT_FEED_CHANNEL_TITLE
+        };
+
+        Self {
+            title,
+            link,
+            description,
+            options,
+
+            // Visitor state
+            xmltv_channels: vec![],
+            feed: JsonFeed::default(),
+        }
+    }
+}
+
+impl Visitor for Json<'_> {
+    type Output = JsonFeed;
+
+    /// Exports from XMLTV TV listing to JSON feed.
+    fn visit_tv(&mut self, _xmltv_listing: &Tv) -> Result<(), Error> {
+        self.feed.version = JSON_FEED_VERSION.to_string();
+        self.feed.title = self.title.to_string();
+        self.feed.home_page_url = Some(self.link)
+            .filter(|link| !link.is_empty())
+            .map(str::to_string);
+        self.feed.description = self
+            .description
+            .unwrap_or(DEFAULT_FEED_CHANNEL_DESCRIPTION)
+            .to_string();
+        self.feed.language = self.options.language.map(str::to_string);
+
+        Ok(())
+    }
+
+    fn visit_channels_start(&mut self) -> Result<(), Error> {
+        self.xmltv_channels.clear();
+
+        Ok(())
+    }
+
+    fn visit_channel(&mut self, xmltv_channel: &xmltv::Channel) -> Result<(), Error> {
+        self.xmltv_channels.push(xmltv_channel.clone());
+
+        Ok(())
+    }
+
+    fn visit_channels_end(&mut self) -> Result<(), Error> {
+        self.feed.icon = match self.xmltv_channels.as_slice() {
+            [channel] => channel
+                .icons
+                .first()
+                .cloned()
+                .filter(|icon| !icon.is_empty()),
+            _ => None,
+        };
+
+        Ok(())
+    }
+
+    fn visit_programmes_start(&mut self) -> Result<(), Error> {
+        self.feed.items.clear();
+
+        Ok(())
+    }
+
+    /// Exports from XMLTV programme to JSON feed item.
+    fn visit_programme(&mut self, xmltv_programme: &Programme) -> Result<(), Error> {
+        self.visit_annotated_programme(xmltv_programme, &Annotations::default())
+    }
+
+    /// Exports from XMLTV programme to JSON feed item, with a tag for each matching watchlist rule
+    /// and the airings of a deduplicated episode.
+    fn visit_annotated_programme(
+        &mut self,
+        xmltv_programme: &Programme,
+        annotations: &Annotations,
+    ) -> Result<(), Error> {
+        // let language = self.options.language;
+        let language = self.options.language.filter(|l| !l.is_empty());
+
+        let channel_id = &xmltv_programme.channel;
+
+        let (starttime_dt, stoptime_dt) = parse_programme_times(xmltv_programme, self.options)?;
+        let starttime_dt = self.options.to_output_timezone(starttime_dt);
+        let stoptime_dt = self.options.to_output_timezone(stoptime_dt);
+
+        //
+
+        let title = find_value(&xmltv_programme.titles, language);
+        let badge_title = match self.options.badges {
+            Some(BadgePlacement::Title) => {
+                title_with_badges(title, &badges(xmltv_programme, self.options))
+            }
+            _ => title.to_string(),
+        };
+
+        let content_html = item_description(
+            &self.xmltv_channels,
+            title,
+            starttime_dt,
+            stoptime_dt,
+            xmltv_programme,
+            annotations,
+            self.options,
+        );
+
+        let summary = find_value(&xmltv_programme.descriptions, language)
+            .trim()
+            .lines()
+            .map(|line| line.trim())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut tags: Vec<String> = vec![];
+        let categories = xmltv_programme
+            .categories
+            .iter()
+            .filter(|category| language.is_none() || category.lang.as_deref() == language)
+            .map(|category| category.name.trim());
+        for tag in categories.chain(annotations.rules.iter().copied()) {
+            if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+
+        let item = JsonItem {
+            id: format!(
+                "{channel_id}-{}",
+                starttime_dt.to_utc().format(GUID_DATETIME_FORMAT)
+            ),
+            url: first_url(&xmltv_programme.urls),
+            title: badge_title,
+            content_html,
+            summary: Some(summary).filter(|summary| !summary.is_empty()),
+            image: xmltv_programme.icons.first().map(|icon| icon.src.clone()),
+            date_published: starttime_dt.to_rfc3339_opts(SecondsFormat::Secs, true),
+            tags,
+        };
+
+        self.feed.items.push(item);
+
+        Ok(())
+    }
+
+    /// Returns the exported JSON feed.
+    fn result(&self) -> Result<Self::Output, Error> {
+        Ok(self.feed.clone())
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Tz;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    use super::*;
+    use crate::export::{self, Timezone};
+    use crate::xmltv::reader::Reader;
+
+    const DEFAULT_JSON_INDENT: usize = 2;
+
+    #[derive(Default)]
+    struct Test<'a> {
+        input_file: &'a str,
+        expected_file: &'a str,
+        language: Option<&'a str>,
+        input_timezone: Option<Tz>,
+        output_timezone: Option<Timezone>,
+    }
+
+    #[test]
+    fn test() {
+        // const TESTS: [Test; _] = [Test {
+        const TESTS: [Test; 5] = [
+            Test {
+                input_file: "tests/input/simple.xml",
+                expected_file: "tests/output/json/simple.json",
+                language: None,
+                input_timezone: None,
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/simple.xml",
+                expected_file: "tests/output/json/simple-language.json",
+                language: Some("fr-FR"),
+                input_timezone: None,
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/json/timezones.json",
+                language: None,
+                input_timezone: None,
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/json/timezones-input-timezone.json",
+                language: None,
+                input_timezone: Some(chrono_tz::Europe::Amsterdam),
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/json/timezones-output-timezone.json",
+                language: None,
+                input_timezone: None,
+                output_timezone: Some(Timezone::Iana(chrono_tz::Europe::Berlin)),
+            },
+        ];
+
+        for test in TESTS.iter() {
+            // Get test values
+            let expected_file = test.expected_file;
+            let input_file = test.input_file;
+
+            let expected = fs::read_to_string(expected_file).unwrap();
+            // fs::read_to_string() adds a newline character at the end of the string
+            let expected = expected.trim_end();
+
+            // Get input arguments
+            let input = fs::read_to_string(input_file).unwrap();
+            let link = "";
+            let options = Options {
+                language: test.language,
+                input_timezone: test.input_timezone,
+                output_timezone: test.output_timezone,
+                ..Default::default()
+            };
+            let xmltv_listing = Reader::new(input.as_bytes()).read_listing().unwrap();
+
+            // Run test
+            let mut visitor = Json::new(DEFAULT_FEED_CHANNEL_TITLE, link, None, &options);
+            let feed = export::export::<JsonFeed>(&mut visitor, &xmltv_listing, &options).unwrap();
+
+            // Run test with the streaming XMLTV reader
+            let mut visitor = Json::new(DEFAULT_FEED_CHANNEL_TITLE, link, None, &options);
+            let streamed_feed =
+                export::export_from_reader::<JsonFeed>(&mut visitor, input.as_bytes(), &options)
+                    .unwrap();
+            assert_eq!(streamed_feed, feed, "for streamed input file {input_file}");
+
+            let mut output = vec![];
+            feed.write_to(&mut output, DEFAULT_JSON_INDENT).unwrap();
+            let output = String::from_utf8(output).unwrap();
+
+            // Check result
+            assert_eq!(output, expected, "for output file {expected_file}");
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use chrono::{DateTime, Local};
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::Writer;
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use xmltv::{Channel, Programme, Tv};
+
+use crate::error::Error;
+use crate::export::{channel_name, Options, Visitor, DEFAULT_FEED_CHANNEL_TITLE};
+use crate::input;
+use crate::split::{ChannelListing, FileStems};
+
+/// Exports an XMLTV TV listing to an OPML document, with an outline per XMLTV channel.
+///
+/// The feed URL of a channel is the base URL followed by the feed file name of the channel,
+/// as written by [`split_by_channel`](crate::split::split_by_channel) with extension ".xml".
+/// If grouped by category, then the channels are grouped by the categories of their programmes.
+pub fn export(
+    title: &str,
+    base_url: &str,
+    group_by_category: bool,
+    options: &Options,
+    file: Option<&str>,
+) -> Result<Opml, Error> {
+    let (reader, date_created) = input::open(file.map(Path::new))?;
+
+    export_from_reader(
+        title,
+        base_url,
+        group_by_category,
+        Some(date_created),
+        options,
+        reader,
+    )
+}
+
+/// Exports an XMLTV TV listing, read from a reader, to an OPML document.
+pub fn export_from_reader(
+    title: &str,
+    base_url: &str,
+    group_by_category: bool,
+    date_created: Option<DateTime<Local>>,
+    options: &Options,
+    reader: impl Read,
+) -> Result<Opml, Error> {
+    let mut visitor = OpmlVisitor::new(title, base_url, group_by_category, date_created, options);
+
+    super::export_from_reader::<Opml>(&mut visitor, BufReader::new(reader), options)
+}
+
+/// Exports a parsed XMLTV TV listing to an OPML document.
+pub fn export_from_tv(
+    title: &str,
+    base_url: &str,
+    group_by_category: bool,
+    date_created: Option<DateTime<Local>>,
+    options: &Options,
+    xmltv_listing: &Tv,
+) -> Result<Opml, Error> {
+    let mut visitor = OpmlVisitor::new(title, base_url, group_by_category, date_created, options);
+
+    super::export::<Opml>(&mut visitor, xmltv_listing, options)
+}
+
+/// Exports the XMLTV TV listings of the channels, as split by
+/// [`split_by_channel`](crate::split::split_by_channel), to an OPML document.
+///
+/// The feed URL of a channel is the base URL followed by the file name of its listing, with extension ".xml".
+/// Unlike [`export_from_tv`], this includes the channels of programmes without `<channel>` element.
+pub fn export_from_channel_listings(
+    title: &str,
+    base_url: &str,
+    group_by_category: bool,
+    date_created: Option<DateTime<Local>>,
+    options: &Options,
+    channel_listings: &[ChannelListing],
+) -> Result<Opml, Error> {
+    let mut visitor = OpmlVisitor::new(title, base_url, group_by_category, date_created, options);
+    for channel_listing in channel_listings {
+        let mut channel_visitor =
+            OpmlVisitor::new(title, base_url, group_by_category, date_created, options);
+        channel_visitor.file_stem = Some(&channel_listing.file_stem);
+        super::export::<Opml>(&mut channel_visitor, &channel_listing.listing, options)?;
+
+        visitor.feeds.append(&mut channel_visitor.feeds);
+        visitor.categories.append(&mut channel_visitor.categories);
+    }
+
+    visitor.result()
+}
+
+//
+
+/// OPML 2.0 document.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Opml {
+    pub title: String,
+    pub date_created: Option<DateTime<Local>>,
+    pub outlines: Vec<Outline>,
+}
+
+/// OPML outline: a feed, or a group of outlines.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Outline {
+    pub text: String,
+
+    /// Feed URL, if the outline is a feed
+    pub xml_url: Option<String>,
+
+    pub outlines: Vec<Outline>,
+}
+
+impl Opml {
+    /// Writes the OPML document, indented with the number of spaces.
+    pub fn write_to(&self, writer: impl Write, indent: usize) -> Result<(), Error> {
+        let mut writer = if indent > 0 {
+            Writer::new_with_indent(writer, b' ', indent)
+        } else {
+            Writer::new(writer)
+        };
+
+        writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+        writer.write_event(Event::Start(
+            BytesStart::new("opml").with_attributes([("version", "2.0")]),
+        ))?;
+
+        writer.write_event(Event::Start(BytesStart::new("head")))?;
+        writer
+            .create_element("title")
+            .write_text_content(BytesText::new(&self.title))?;
+        if let Some(date_created) = self.date_created {
+            writer
+                .create_element("dateCreated")
+                .write_text_content(BytesText::new(&date_created.to_rfc2822()))?;
+        }
+        writer.write_event(Event::End(BytesEnd::new("head")))?;
+
+        writer.write_event(Event::Start(BytesStart::new("body")))?;
+        for outline in &self.outlines {
+            outline.write_to(&mut writer)?;
+        }
+        writer.write_event(Event::End(BytesEnd::new("body")))?;
+
+        writer.write_event(Event::End(BytesEnd::new("opml")))?;
+        writer.into_inner().flush()?;
+
+        Ok(())
+    }
+}
+
+impl Outline {
+    fn write_to(&self, writer: &mut Writer<impl Write>) -> Result<(), Error> {
+        let mut start = BytesStart::new("outline");
+        start.push_attribute(("text", self.text.as_str()));
+        if let Some(xml_url) = &self.xml_url {
+            start.push_attribute(("type", "rss"));
+            start.push_attribute(("title", self.text.as_str()));
+            start.push_attribute(("xmlUrl", xml_url.as_str()));
+        }
+
+        if self.outlines.is_empty() {
+            writer.write_event(Event::Empty(start))?;
+        } else {
+            writer.write_event(Event::Start(start))?;
+            for outline in &self.outlines {
+                outline.write_to(writer)?;
+            }
+            writer.write_event(Event::End(BytesEnd::new("outline")))?;
+        }
+
+        Ok(())
+    }
+}
+
+//
+
+/// OPML document export struct.
+pub(crate) struct OpmlVisitor<'a> {
+    title: &'a str,
+    base_url: &'a str,
+    group_by_category: bool,
+    date_created: Option<DateTime<Local>>,
+    options: &'a Options<'a>,
+    /// Feed file name without extension of the only channel, instead of a file name derived from the channel id
+    file_stem: Option<&'a str>,
+
+    // Visitor state
+    file_stems: FileStems,
+    /// Feed outlines, in channel order, by channel id
+    feeds: Vec<(String, Outline)>,
+    /// Programme categories, by channel id
+    categories: BTreeMap<String, Vec<String>>,
+}
+
+impl<'a> OpmlVisitor<'a> {
+    pub fn new(
+        title: &'a str,
+        base_url: &'a str,
+        group_by_category: bool,
+        date_created: Option<DateTime<Local>>,
+        options: &'a Options,
+    ) -> Self {
+        let title = if !title.is_empty() {
+            title
+        } else {
+            DEFAULT_FEED_CHANNEL_TITLE
+        };
+
+        Self {
+            title,
+            base_url,
+            group_by_category,
+            date_created,
+            options,
+            file_stem: None,
+
+            // Visitor state
+            file_stems: FileStems::default(),
+            feeds: vec![],
+            categories: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the feed URL of the channel.
+    fn xml_url(&mut self, channel_id: &str) -> String {
+        let base_url = self.base_url;
+        let separator = if base_url.is_empty() || base_url.ends_with('/') {
+            ""
+        } else {
+            "/"
+        };
+        let file_stem = match self.file_stem {
+            Some(file_stem) => file_stem.to_string(),
+            None => self.file_stems.next(channel_id),
+        };
+
+        format!("{base_url}{separator}{file_stem}.xml")
+    }
+}
+
+impl Visitor for OpmlVisitor<'_> {
+    type Output = Opml;
+
+    fn visit_channels_start(&mut self) -> Result<(), Error> {
+        self.file_stems = FileStems::default();
+        self.feeds.clear();
+        self.categories.clear();
+
+        Ok(())
+    }
+
+    /// Exports from XMLTV channel to OPML feed outline.
+    fn visit_channel(&mut self, xmltv_channel: &Channel) -> Result<(), Error> {
+        let language = self.options.language.filter(|l| !l.is_empty());
+
+        let text = channel_name(xmltv_channel, language);
+
+        let outline = Outline {
+            text: text.to_string(),
+            xml_url: Some(self.xml_url(&xmltv_channel.id)),
+            outlines: vec![],
+        };
+        self.feeds.push((xmltv_channel.id.clone(), outline));
+
+        Ok(())
+    }
+
+    /// Collects the categories of the programme, for the channel.
+    fn visit_programme(&mut self, xmltv_programme: &Programme) -> Result<(), Error> {
+        if !self.group_by_category {
+            return Ok(());
+        }
+
+        let categories = self
+            .categories
+            .entry(xmltv_programme.channel.clone())
+            .or_default();
+        for category in &xmltv_programme.categories {
+            let category = category.name.trim();
+            if !category.is_empty() && !categories.iter().any(|c| c == category) {
+                categories.push(category.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the exported OPML document.
+    ///
+    /// If grouped by category, then a channel is in the group of each category of its programmes,
+    /// and the groups are in category name order, followed by the channels without categories.
+    fn result(&self) -> Result<Self::Output, Error> {
+        let outlines = if self.group_by_category {
+            let mut groups: BTreeMap<&str, Vec<Outline>> = BTreeMap::new();
+            let mut ungrouped = vec![];
+            for (channel_id, outline) in &self.feeds {
+                match self.categories.get(channel_id) {
+                    Some(categories) if !categories.is_empty() => {
+                        for category in categories {
+                            groups.entry(category).or_default().push(outline.clone());
+                        }
+                    }
+                    _ => ungrouped.push(outline.clone()),
+                }
+            }
+
+            groups
+                .into_iter()
+                .map(|(category, outlines)| Outline {
+                    text: category.to_string(),
+                    xml_url: None,
+                    outlines,
+                })
+                .chain(ungrouped)
+                .collect()
+        } else {
+            self.feeds
+                .iter()
+                .map(|(_, outline)| outline.clone())
+                .collect()
+        };
+
+        Ok(Opml {
+            title: self.title.to_string(),
+            date_created: self.date_created,
+            outlines,
+        })
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const INPUT: &str = r#"<tv>
+  <channel id="a.tv"><display-name>A &amp; TV</display-name></channel>
+  <channel id="b/tv"><display-name>B TV</display-name></channel>
+  <channel id="c.tv"><display-name>C TV</display-name></channel>
+  <programme channel="a.tv" start="20240101100000 +0000"><title>A</title><category>News</category></programme>
+  <programme channel="b/tv" start="20240101100000 +0000"><title>B</title><category>Film</category><category>News</category></programme>
+</tv>"#;
+
+    fn write(opml: &Opml) -> String {
+        let mut output = vec![];
+        opml.write_to(&mut output, 2).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test() {
+        let options = Options::default();
+        let opml = export_from_reader(
+            "Feeds",
+            "https://example.com/feeds",
+            false,
+            None,
+            &options,
+            INPUT.as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            write(&opml),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0">
+  <head>
+    <title>Feeds</title>
+  </head>
+  <body>
+    <outline text="A &amp; TV" type="rss" title="A &amp; TV" xmlUrl="https://example.com/feeds/a.tv.xml"/>
+    <outline text="B TV" type="rss" title="B TV" xmlUrl="https://example.com/feeds/b_tv.xml"/>
+    <outline text="C TV" type="rss" title="C TV" xmlUrl="https://example.com/feeds/c.tv.xml"/>
+  </body>
+</opml>"#
+        );
+
+        let opml = export_from_reader("Feeds", "", true, None, &options, INPUT.as_bytes()).unwrap();
+        let groups: Vec<_> = opml
+            .outlines
+            .iter()
+            .map(|outline| {
+                let texts: Vec<_> = outline.outlines.iter().map(|o| o.text.as_str()).collect();
+                (outline.text.as_str(), outline.xml_url.as_deref(), texts)
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                ("Film", None, vec!["B TV"]),
+                ("News", None, vec!["A & TV", "B TV"]),
+                ("C TV", Some("c.tv.xml"), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_from_channel_listings() {
+        const INPUT: &str = r#"<tv>
+  <channel id="a.tv"><display-name>A TV</display-name></channel>
+  <channel id="A.TV"><display-name>Other A TV</display-name></channel>
+  <programme channel="a.tv" start="20240101100000 +0000"><title>A</title></programme>
+  <programme channel="d.tv" start="20240101100000 +0000"><title>D</title><category>News</category></programme>
+</tv>"#;
+        let options = Options::default();
+        let listing = crate::xmltv::reader::Reader::new(INPUT.as_bytes())
+            .read_listing()
+            .unwrap();
+        let channel_listings = crate::split::split_by_channel(&listing, &options);
+
+        let opml =
+            export_from_channel_listings("Feeds", "", true, None, &options, &channel_listings)
+                .unwrap();
+        let feeds: Vec<_> = opml
+            .outlines
+            .iter()
+            .flat_map(|outline| {
+                outline
+                    .xml_url
+                    .iter()
+                    .chain(outline.outlines.iter().flat_map(|outline| &outline.xml_url))
+            })
+            .map(String::as_str)
+            .collect();
+        let file_names: Vec<_> = channel_listings
+            .iter()
+            .map(|channel_listing| format!("{}.xml", channel_listing.file_stem))
+            .collect();
+        assert_eq!(feeds, ["d.tv.xml", "a.tv.xml", "A.TV-2.xml"]);
+        assert_eq!(file_names, ["a.tv.xml", "A.TV-2.xml", "d.tv.xml"]);
+        assert_eq!(opml.outlines[0].text, "News");
+    }
+}
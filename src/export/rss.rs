@@ -1,47 +1,90 @@
-use chrono::FixedOffset;
 use chrono::{DateTime, Local};
-use quick_xml::de::from_str;
-use rss::{Channel, ChannelBuilder, Guid, Item, ItemBuilder};
-use std::fs;
-use std::io;
+use rss::{Category, Channel, ChannelBuilder, Guid, Item, ItemBuilder};
+use std::io::{BufReader, Read};
+use std::path::Path;
 use xmltv::{Programme, Tv};
 
+use crate::badge::{badges, title_with_badges, BadgePlacement};
 use crate::error::Error;
-use crate::export::{Options, Visitor, GUID_DATETIME_FORMAT};
-use crate::export::{DEFAULT_FEED_CHANNEL_DESCRIPTION, DEFAULT_FEED_CHANNEL_TITLE};
-use crate::xmltv::DEFAULT_XMLTV_DATETIME_FORMAT_UTC;
-use crate::xmltv::{find_name, find_value, first_url, parse_from_str};
+use crate::export::{item_description, Annotations, Options, Visitor};
+use crate::export::{
+    DEFAULT_FEED_CHANNEL_DESCRIPTION, DEFAULT_FEED_CHANNEL_TITLE, GUID_DATETIME_FORMAT,
+};
+use crate::input;
+use crate::xmltv::{find_value, first_url, parse_programme_times};
 
 /// Exports an XMLTV TV listing to an RSS channel/feed.
+///
+/// The publication date of the feed is the modification time of the file, or else the current time.
 pub fn export(
     title: &str,
     link: &str,
     description: Option<&str>,
     options: &Options,
-    // reader: &mut impl Read,
     file: Option<&str>,
 ) -> Result<Channel, Error> {
-    let last_build_date = Local::now();
-    let (xmltv_listing, pub_date) = match &file {
-        Some(file) if *file != "-" => (
-            fs::read_to_string(file)?,
-            DateTime::<Local>::from(fs::metadata(file)?.modified()?),
-        ),
-        _ => (io::read_to_string(io::stdin())?, last_build_date),
-    };
-    let xmltv_listing: Tv = from_str(&xmltv_listing)?;
-
-    let mut visitor = Rss::new(
+    let (reader, pub_date) = input::open(file.map(Path::new))?;
+
+    export_from_reader(
         title,
         link,
         description,
         Some(pub_date),
-        Some(last_build_date),
+        Some(Local::now()),
         options,
-        &xmltv_listing.channels,
-    );
+        reader,
+    )
+}
 
-    super::export::<Channel>(&mut visitor, &xmltv_listing)
+/// Exports an XMLTV TV listing, read from a reader, to an RSS channel/feed.
+pub fn export_from_reader(
+    title: &str,
+    link: &str,
+    description: Option<&str>,
+    pub_date: Option<DateTime<Local>>,
+    last_build_date: Option<DateTime<Local>>,
+    options: &Options,
+    reader: impl Read,
+) -> Result<Channel, Error> {
+    let mut visitor = Rss::new(title, link, description, pub_date, last_build_date, options);
+
+    super::export_from_reader::<Channel>(&mut visitor, BufReader::new(reader), options)
+}
+
+/// Exports an XMLTV TV listing string to an RSS channel/feed.
+pub fn export_from_str(
+    title: &str,
+    link: &str,
+    description: Option<&str>,
+    pub_date: Option<DateTime<Local>>,
+    last_build_date: Option<DateTime<Local>>,
+    options: &Options,
+    xmltv_listing: &str,
+) -> Result<Channel, Error> {
+    export_from_reader(
+        title,
+        link,
+        description,
+        pub_date,
+        last_build_date,
+        options,
+        xmltv_listing.as_bytes(),
+    )
+}
+
+/// Exports a parsed XMLTV TV listing to an RSS channel/feed.
+pub fn export_from_tv(
+    title: &str,
+    link: &str,
+    description: Option<&str>,
+    pub_date: Option<DateTime<Local>>,
+    last_build_date: Option<DateTime<Local>>,
+    options: &Options,
+    xmltv_listing: &Tv,
+) -> Result<Channel, Error> {
+    let mut visitor = Rss::new(title, link, description, pub_date, last_build_date, options);
+
+    super::export::<Channel>(&mut visitor, xmltv_listing, options)
 }
 
 //
@@ -56,7 +99,7 @@ pub(crate) struct Rss<'a> {
     options: &'a Options<'a>,
 
     // Visitor state
-    xmltv_channels: &'a Vec<xmltv::Channel>,
+    xmltv_channels: Vec<xmltv::Channel>,
     channel: ChannelBuilder,
     items: Vec<Item>,
 }
@@ -69,9 +112,6 @@ impl<'a> Rss<'a> {
         pub_date: Option<DateTime<Local>>,
         last_build_date: Option<DateTime<Local>>,
         options: &'a Options,
-
-        // Input data
-        xmltv_channels: &'a Vec<xmltv::Channel>,
     ) -> Self {
         let title = if !title.is_empty() {
             title
@@ -88,70 +128,11 @@ impl<'a> Rss<'a> {
             options,
 
             // Visitor state
-            xmltv_channels,
+            xmltv_channels: vec![],
             channel: ChannelBuilder::default(),
             items: vec![],
         }
     }
-
-    fn item_description(
-        &mut self,
-        language: Option<&str>,
-        title: &str,
-        channel_id: &str,
-        starttime_dt: DateTime<FixedOffset>,
-        stoptime_dt: DateTime<FixedOffset>,
-        xmltv_programme: &Programme,
-    ) -> Result<String, Error> {
-        let channel = if let Some(channel_callsign) = self
-            .xmltv_channels
-            .iter()
-            .find(|channel| channel.id == *channel_id)
-        {
-            let display_name = find_name(&channel_callsign.display_names, language);
-            format!("{channel_id}-{display_name}")
-        } else {
-            channel_id.to_string()
-        };
-
-        let airdate = format!("{}", starttime_dt.format(self.options.date_format));
-        let airtime = format!(
-            "{} - {}",
-            starttime_dt.format(self.options.time_format),
-            stoptime_dt.format(self.options.time_format)
-        );
-
-        let airtime_length_td = stoptime_dt - starttime_dt;
-        let airtime_length_mins = airtime_length_td.num_seconds() / 60;
-        let airtime_length = format!(
-            "{:02}:{:02}:00",
-            airtime_length_mins / 60,
-            airtime_length_mins % 60
-        );
-
-        let category = find_name(&xmltv_programme.categories, language);
-
-        let desc = find_value(&xmltv_programme.descriptions, language);
-        let desc = desc
-            .trim()
-            .lines()
-            .map(|line| line.trim())
-            .collect::<Vec<_>>()
-            .join("<br/>");
-
-        let description = format!("\
-<table>\
-<tr><td align=\"right\" valign=\"top\">Title:</td><td>{title}</td></tr>\
-<tr><td align=\"right\" valign=\"top\">Channel:</td><td>{channel}</td></tr>\
-<tr><td align=\"right\" valign=\"top\">Airdate:</td><td>{airdate}</td></tr>\
-<tr><td align=\"right\" valign=\"top\">Airtime:</td><td>{airtime}</td></tr>\
-<tr><td align=\"right\" valign=\"top\" style=\"white-space: nowrap\">Length:</td><td>{airtime_length}</td></tr>\
-<tr><td align=\"right\" valign=\"top\">Category:</td><td>{category}</td></tr>\
-<tr><td align=\"right\" valign=\"top\">Description:</td><td>{desc}</td></tr>\
-</table>");
-
-        Ok(description)
-    }
 }
 
 impl Visitor for Rss<'_> {
@@ -168,15 +149,31 @@ impl Visitor for Rss<'_> {
             self.channel.language(language.to_string());
         }
         if let Some(pub_date) = self.pub_date {
+            let pub_date = self.options.to_output_timezone(pub_date.fixed_offset());
             self.channel.pub_date(pub_date.to_rfc2822());
         }
         if let Some(last_build_date) = self.last_build_date {
+            let last_build_date = self
+                .options
+                .to_output_timezone(last_build_date.fixed_offset());
             self.channel.last_build_date(last_build_date.to_rfc2822());
         }
 
         Ok(())
     }
 
+    fn visit_channels_start(&mut self) -> Result<(), Error> {
+        self.xmltv_channels.clear();
+
+        Ok(())
+    }
+
+    fn visit_channel(&mut self, xmltv_channel: &xmltv::Channel) -> Result<(), Error> {
+        self.xmltv_channels.push(xmltv_channel.clone());
+
+        Ok(())
+    }
+
     fn visit_programmes_start(&mut self) -> Result<(), Error> {
         self.items.clear();
 
@@ -185,58 +182,72 @@ impl Visitor for Rss<'_> {
 
     /// Exports from XMLTV programme to RSS item.
     fn visit_programme(&mut self, xmltv_programme: &Programme) -> Result<(), Error> {
+        self.visit_annotated_programme(xmltv_programme, &Annotations::default())
+    }
+
+    /// Exports from XMLTV programme to RSS item, with a category for each matching watchlist rule
+    /// and the airings of a deduplicated episode.
+    fn visit_annotated_programme(
+        &mut self,
+        xmltv_programme: &Programme,
+        annotations: &Annotations,
+    ) -> Result<(), Error> {
         // let language = self.options.language;
         let language = self.options.language.filter(|l| !l.is_empty());
 
         let channel_id = &xmltv_programme.channel;
 
-        let starttime = &xmltv_programme.start;
-        let stoptime = match &xmltv_programme.stop {
-            Some(time) => time,
-            None => starttime,
-        };
-
-        let starttime_dt = parse_from_str(
-            starttime,
-            self.options.xmltv_datetime_format,
-            DEFAULT_XMLTV_DATETIME_FORMAT_UTC,
-        )?;
-        let stoptime_dt = parse_from_str(
-            stoptime,
-            self.options.xmltv_datetime_format,
-            DEFAULT_XMLTV_DATETIME_FORMAT_UTC,
-        )?;
+        let (starttime_dt, stoptime_dt) = parse_programme_times(xmltv_programme, self.options)?;
+        let starttime_dt = self.options.to_output_timezone(starttime_dt);
+        let stoptime_dt = self.options.to_output_timezone(stoptime_dt);
 
         //
 
         let title = find_value(&xmltv_programme.titles, language);
+        let badge_title = match self.options.badges {
+            Some(BadgePlacement::Title) => {
+                title_with_badges(title, &badges(xmltv_programme, self.options))
+            }
+            _ => title.to_string(),
+        };
 
         let link = first_url(&xmltv_programme.urls);
 
-        let description = self.item_description(
-            language,
+        let description = item_description(
+            &self.xmltv_channels,
             title,
-            channel_id,
             starttime_dt,
             stoptime_dt,
             xmltv_programme,
-        )?;
+            annotations,
+            self.options,
+        );
 
         let mut guid = Guid::default();
         guid.set_value(format!(
             "{channel_id}-{}",
-            starttime_dt.format(GUID_DATETIME_FORMAT)
+            starttime_dt.to_utc().format(GUID_DATETIME_FORMAT)
         ));
         let guid = guid;
 
         let pub_date = starttime_dt.to_rfc2822();
 
         let item = ItemBuilder::default()
-            .title(title.to_string())
+            .title(badge_title)
             .link(link)
             .description(description.to_string())
             .guid(Some(guid))
             .pub_date(pub_date)
+            .categories(
+                annotations
+                    .rules
+                    .iter()
+                    .map(|rule| Category {
+                        name: rule.to_string(),
+                        domain: None,
+                    })
+                    .collect::<Vec<_>>(),
+            )
             .build();
 
         self.items.push(item);
@@ -261,13 +272,14 @@ impl Visitor for Rss<'_> {
 
 #[cfg(test)]
 mod tests {
+    use chrono_tz::Tz;
     use pretty_assertions::assert_eq;
     use quick_xml::de::from_str;
     use rss::Channel;
     use std::fs;
 
     use super::*;
-    use crate::export;
+    use crate::export::{self, Timezone};
 
     const DEFAULT_XML_INDENT: usize = 2;
     const LAST_BUILD_DATE: &str = "Tue, 30 Apr 2024 12:00:00 +0000";
@@ -278,26 +290,48 @@ mod tests {
         input_file: &'a str,
         expected_file: &'a str,
         language: Option<&'a str>,
+        input_timezone: Option<Tz>,
+        output_timezone: Option<Timezone>,
     }
 
     #[test]
     fn test() {
         // const TESTS: [Test; _] = [Test {
-        const TESTS: [Test; 3] = [
+        const TESTS: [Test; 5] = [
             Test {
                 input_file: "tests/input/simple.xml",
                 expected_file: "tests/output/rss/simple.xml",
                 language: None,
+                input_timezone: None,
+                output_timezone: None,
             },
             Test {
                 input_file: "tests/input/simple.xml",
                 expected_file: "tests/output/rss/simple-language.xml",
                 language: Some("fr-FR"),
+                input_timezone: None,
+                output_timezone: None,
             },
             Test {
                 input_file: "tests/input/timezones.xml",
                 expected_file: "tests/output/rss/timezones.xml",
                 language: None,
+                input_timezone: None,
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/rss/timezones-input-timezone.xml",
+                language: None,
+                input_timezone: Some(chrono_tz::Europe::Amsterdam),
+                output_timezone: None,
+            },
+            Test {
+                input_file: "tests/input/timezones.xml",
+                expected_file: "tests/output/rss/timezones-output-timezone.xml",
+                language: None,
+                input_timezone: None,
+                output_timezone: Some(Timezone::Iana(chrono_tz::Europe::Berlin)),
             },
         ];
 
@@ -322,6 +356,8 @@ mod tests {
             // let options = Options::default();
             let options = Options {
                 language: test.language,
+                input_timezone: test.input_timezone,
+                output_timezone: test.output_timezone,
                 ..Default::default()
             };
             let xmltv_listing: Tv = from_str(&input).unwrap();
@@ -334,9 +370,26 @@ mod tests {
                 Some(pub_date.into()),
                 Some(last_build_date.into()),
                 &options,
-                &xmltv_listing.channels,
             );
-            let channel = export::export::<Channel>(&mut visitor, &xmltv_listing).unwrap();
+            let channel =
+                export::export::<Channel>(&mut visitor, &xmltv_listing, &options).unwrap();
+
+            // Run test with the streaming XMLTV reader
+            let mut visitor = Rss::new(
+                DEFAULT_FEED_CHANNEL_TITLE,
+                link,
+                None,
+                Some(pub_date.into()),
+                Some(last_build_date.into()),
+                &options,
+            );
+            let streamed_channel =
+                export::export_from_reader::<Channel>(&mut visitor, input.as_bytes(), &options)
+                    .unwrap();
+            assert_eq!(
+                streamed_channel, channel,
+                "for streamed input file {input_file}"
+            );
 
             let output = String::from_utf8(
                 channel
@@ -350,4 +403,40 @@ mod tests {
             assert_eq!(output, expected, "for output file {expected_file}");
         }
     }
+
+    #[test]
+    fn test_export_from_str() {
+        let input = fs::read_to_string("tests/input/simple.xml").unwrap();
+        let pub_date = DateTime::parse_from_rfc2822(PUB_DATE).unwrap();
+        let last_build_date = DateTime::parse_from_rfc2822(LAST_BUILD_DATE).unwrap();
+        let options = Options {
+            output_timezone: Some(Timezone::Iana(chrono_tz::UTC)),
+            ..Default::default()
+        };
+
+        let channel = export_from_str(
+            DEFAULT_FEED_CHANNEL_TITLE,
+            "",
+            None,
+            Some(pub_date.into()),
+            Some(last_build_date.into()),
+            &options,
+            &input,
+        )
+        .unwrap();
+        assert_eq!(channel.pub_date(), Some(PUB_DATE));
+        assert_eq!(channel.last_build_date(), Some(LAST_BUILD_DATE));
+
+        let channel = export_from_str(
+            DEFAULT_FEED_CHANNEL_TITLE,
+            "",
+            None,
+            None,
+            None,
+            &options,
+            &input,
+        )
+        .unwrap();
+        assert_eq!(channel.last_build_date(), None);
+    }
 }
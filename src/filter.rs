@@ -0,0 +1,541 @@
+use chrono::{DateTime, Days, FixedOffset, NaiveDate};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+use std::collections::HashMap;
+use std::str::FromStr;
+use xmltv::{Channel, Programme};
+
+use crate::export::Options;
+use crate::watchlist::WatchlistMatcher;
+
+/// Channel pattern, matched against the XMLTV channel id and display names.
+///
+/// Syntax: `[id:|name:][regex:]PATTERN`.
+///
+/// The pattern matches the channel id or any display name, or with prefix "id:" the channel id only,
+/// or with prefix "name:" the display names only. The pattern is a case-insensitive glob pattern
+/// ("*", "?", "[...]"), or with prefix "regex:" a regular expression.
+#[derive(Clone, Debug)]
+pub struct ChannelPattern {
+    field: ChannelField,
+    matcher: Matcher,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ChannelField {
+    Any,
+    Id,
+    Name,
+}
+
+#[derive(Clone, Debug)]
+enum Matcher {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl ChannelPattern {
+    /// Returns true if the channel matches the pattern.
+    pub fn is_match(&self, channel: &Channel) -> bool {
+        let id = || self.matches(&channel.id);
+        let name = || {
+            channel
+                .display_names
+                .iter()
+                .any(|name| self.matches(name.name.trim()))
+        };
+
+        match self.field {
+            ChannelField::Any => id() || name(),
+            ChannelField::Id => id(),
+            ChannelField::Name => name(),
+        }
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match &self.matcher {
+            Matcher::Glob(glob) => glob.is_match(value),
+            Matcher::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+impl FromStr for ChannelPattern {
+    type Err = String;
+
+    /// Parses a channel pattern. See [`ChannelPattern`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, pattern) = if let Some(pattern) = s.strip_prefix("id:") {
+            (ChannelField::Id, pattern)
+        } else if let Some(pattern) = s.strip_prefix("name:") {
+            (ChannelField::Name, pattern)
+        } else {
+            (ChannelField::Any, s)
+        };
+
+        let matcher = if let Some(pattern) = pattern.strip_prefix("regex:") {
+            Matcher::Regex(Regex::new(pattern).map_err(|err| format!("{err}"))?)
+        } else {
+            let glob = GlobBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|err| format!("{err}"))?;
+            Matcher::Glob(glob.compile_matcher())
+        };
+
+        Ok(Self { field, matcher })
+    }
+}
+
+/// Filter of the channels and programmes to export.
+pub(crate) struct Filter<'a> {
+    pub(crate) channels: ChannelFilter<'a>,
+    pub(crate) time_window: TimeWindow,
+    pub(crate) airing: AiringFilter,
+    pub(crate) limits: Limits,
+    pub(crate) watchlist: Option<WatchlistMatcher<'a>>,
+}
+
+impl<'a> Filter<'a> {
+    pub(crate) fn new(options: &Options<'a>) -> Self {
+        Self {
+            channels: ChannelFilter::new(options),
+            time_window: TimeWindow::new(options),
+            airing: AiringFilter::new(options),
+            limits: Limits::new(options),
+            watchlist: options.watchlist.map(WatchlistMatcher::new),
+        }
+    }
+
+    /// Returns true if the channel is accepted.
+    pub(crate) fn accept_channel(&mut self, channel: &Channel) -> bool {
+        if let Some(watchlist) = &mut self.watchlist {
+            watchlist.visit_channel(channel);
+        }
+
+        self.channels.accept_channel(channel)
+    }
+}
+
+/// Channels accepted by the channel filter of [`Options::include_channels`] and [`Options::exclude_channels`].
+///
+/// A channel is accepted if it matches any include pattern, or if there are none,
+/// and does not match any exclude pattern.
+pub(crate) struct ChannelFilter<'a> {
+    include: &'a [ChannelPattern],
+    exclude: &'a [ChannelPattern],
+
+    /// Accepted or not, by channel id
+    accepted: HashMap<String, bool>,
+}
+
+impl<'a> ChannelFilter<'a> {
+    pub(crate) fn new(options: &Options<'a>) -> Self {
+        Self {
+            include: options.include_channels,
+            exclude: options.exclude_channels,
+            accepted: HashMap::new(),
+        }
+    }
+
+    /// Returns true if the channel is accepted.
+    pub(crate) fn accept_channel(&mut self, channel: &Channel) -> bool {
+        let accepted = self.is_match(channel);
+        self.accepted.insert(channel.id.clone(), accepted);
+        accepted
+    }
+
+    /// Returns true if the channel with the id, for example of a programme, is accepted.
+    ///
+    /// A channel without `<channel>` element is matched by its id only.
+    pub(crate) fn accept_channel_id(&mut self, id: &str) -> bool {
+        if let Some(&accepted) = self.accepted.get(id) {
+            return accepted;
+        }
+
+        let channel = Channel {
+            id: id.to_string(),
+            ..Default::default()
+        };
+        self.accept_channel(&channel)
+    }
+
+    fn is_match(&self, channel: &Channel) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.is_match(channel)))
+            && !self.exclude.iter().any(|p| p.is_match(channel))
+    }
+}
+
+/// Time window of [`Options::from`], [`Options::until`], [`Options::next`] and [`Options::drop_ended`].
+///
+/// A programme is accepted if it airs, or starts, within the time window.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct TimeWindow {
+    from: Option<DateTime<FixedOffset>>,
+    until: Option<DateTime<FixedOffset>>,
+}
+
+impl TimeWindow {
+    pub(crate) fn new(options: &Options) -> Self {
+        let now = options.now();
+
+        let from = [
+            options.from,
+            (options.drop_ended || options.next.is_some()).then_some(now),
+        ];
+        // An end beyond the supported dates is no end
+        let until = [
+            options.until,
+            options.next.and_then(|next| now.checked_add_signed(next)),
+        ];
+
+        Self {
+            from: from.into_iter().flatten().max(),
+            until: until.into_iter().flatten().min(),
+        }
+    }
+
+    /// Returns true if the programme airtime is within the time window.
+    ///
+    /// A programme is within the time window if it stops after the start of the time window,
+    /// or starts at the start of the time window if it has no length,
+    /// and if it starts before the end of the time window.
+    pub(crate) fn accept(
+        &self,
+        starttime_dt: DateTime<FixedOffset>,
+        stoptime_dt: DateTime<FixedOffset>,
+    ) -> bool {
+        self.from
+            .is_none_or(|from| stoptime_dt > from || starttime_dt >= from)
+            && self.until.is_none_or(|until| starttime_dt < until)
+    }
+}
+
+/// Airing filter of [`Options::only_new`], [`Options::only_premieres`] and [`Options::drop_repeats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct AiringFilter {
+    only_new: bool,
+    only_premieres: bool,
+    drop_repeats: bool,
+}
+
+impl AiringFilter {
+    pub(crate) fn new(options: &Options) -> Self {
+        Self {
+            only_new: options.only_new,
+            only_premieres: options.only_premieres,
+            drop_repeats: options.drop_repeats,
+        }
+    }
+
+    /// Returns true if the programme is accepted.
+    pub(crate) fn accept(&self, programme: &Programme) -> bool {
+        (!self.only_new || programme.new || programme.premiere.is_some())
+            && (!self.only_premieres || programme.premiere.is_some())
+            && (!self.drop_repeats || programme.previously_shown.is_none())
+    }
+}
+
+/// Item limits of [`Options::max_items`], [`Options::max_items_per_channel`] and [`Options::max_days`].
+///
+/// The limits apply to the programmes in export order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Limits {
+    max_items: Option<usize>,
+    max_items_per_channel: Option<usize>,
+    max_days: Option<u64>,
+
+    /// Start day of the earliest exported programme, for the maximum number of days
+    pub(crate) first_day: Option<NaiveDate>,
+
+    /// Number of accepted programmes, in total and by channel id
+    items: usize,
+    channel_items: HashMap<String, usize>,
+}
+
+impl Limits {
+    pub(crate) fn new(options: &Options) -> Self {
+        Self {
+            max_items: options.max_items,
+            max_items_per_channel: options.max_items_per_channel,
+            max_days: options.max_days,
+            ..Default::default()
+        }
+    }
+
+    /// Returns true if the programme on the channel and starting at the time is accepted, and counts it.
+    pub(crate) fn accept(&mut self, channel: &str, starttime_dt: DateTime<FixedOffset>) -> bool {
+        if self
+            .max_items
+            .is_some_and(|max_items| self.items >= max_items)
+        {
+            return false;
+        }
+
+        let day = starttime_dt.date_naive();
+        let first_day = *self.first_day.get_or_insert(day);
+        // An end day beyond the supported dates is no limit
+        if self.max_days.is_some_and(|max_days| {
+            first_day
+                .checked_add_days(Days::new(max_days))
+                .is_some_and(|end_day| day >= end_day)
+        }) {
+            return false;
+        }
+
+        let channel_items = self.channel_items.entry(channel.to_string()).or_default();
+        if self
+            .max_items_per_channel
+            .is_some_and(|max_items| *channel_items >= max_items)
+        {
+            return false;
+        }
+
+        *channel_items += 1;
+        self.items += 1;
+        true
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+    use xmltv::{NameAndLang, PreviouslyShown, ValueAndLang};
+
+    use super::*;
+
+    fn channel(id: &str, name: &str) -> Channel {
+        Channel {
+            id: id.to_string(),
+            display_names: vec![NameAndLang {
+                name: name.to_string(),
+                lang: None,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_channel_pattern() {
+        let bbc_one = channel("bbc1.bbc.co.uk", "BBC One");
+        let npo_1 = channel("npo1.nl", "NPO 1");
+
+        const TESTS: [(&str, bool, bool); 8] = [
+            ("bbc1.bbc.co.uk", true, false),
+            ("BBC One", true, false),
+            ("*.nl", false, true),
+            ("bbc*", true, false),
+            ("id:BBC*", true, false),
+            ("name:*.nl", false, false),
+            ("regex:^(BBC|NPO) 1$", false, true),
+            ("name:regex:(?i)^bbc", true, false),
+        ];
+        for (pattern, bbc_one_match, npo_1_match) in TESTS {
+            let pattern: ChannelPattern = pattern.parse().unwrap();
+            assert_eq!(pattern.is_match(&bbc_one), bbc_one_match, "for {pattern:?}");
+            assert_eq!(pattern.is_match(&npo_1), npo_1_match, "for {pattern:?}");
+        }
+
+        assert!("regex:(".parse::<ChannelPattern>().is_err());
+    }
+
+    #[test]
+    fn test_channel_filter() {
+        let include = ["*.nl".parse().unwrap()];
+        let exclude = ["name:NPO 2".parse().unwrap()];
+        let options = Options {
+            include_channels: &include,
+            exclude_channels: &exclude,
+            ..Default::default()
+        };
+        let mut filter = ChannelFilter::new(&options);
+
+        assert!(filter.accept_channel(&channel("npo1.nl", "NPO 1")));
+        assert!(!filter.accept_channel(&channel("npo2.nl", "NPO 2")));
+        assert!(!filter.accept_channel(&channel("bbc1.bbc.co.uk", "BBC One")));
+
+        assert!(filter.accept_channel_id("npo1.nl"));
+        assert!(!filter.accept_channel_id("npo2.nl"));
+        // Without <channel> element
+        assert!(filter.accept_channel_id("npo3.nl"));
+        assert!(!filter.accept_channel_id("bbc2.bbc.co.uk"));
+    }
+
+    #[test]
+    fn test_time_window() {
+        let datetime = |datetime| DateTime::parse_from_rfc3339(datetime).unwrap();
+        let now = datetime("2024-01-01T12:00:00+00:00");
+
+        // Programme start and stop times
+        let ended = (
+            datetime("2024-01-01T10:00:00+00:00"),
+            datetime("2024-01-01T11:00:00+00:00"),
+        );
+        let airing = (
+            datetime("2024-01-01T11:30:00+00:00"),
+            datetime("2024-01-01T12:30:00+00:00"),
+        );
+        let today = (
+            datetime("2024-01-01T20:00:00+00:00"),
+            datetime("2024-01-01T21:00:00+00:00"),
+        );
+        let tomorrow = (
+            datetime("2024-01-02T20:00:00+00:00"),
+            datetime("2024-01-02T21:00:00+00:00"),
+        );
+        let now_without_length = (now, now);
+
+        let tests = [
+            (Options::default(), [true, true, true, true, true]),
+            (
+                Options {
+                    drop_ended: true,
+                    now: Some(now),
+                    ..Default::default()
+                },
+                [false, true, true, true, true],
+            ),
+            (
+                Options {
+                    next: Some(TimeDelta::hours(24)),
+                    now: Some(now),
+                    ..Default::default()
+                },
+                [false, true, true, false, true],
+            ),
+            (
+                Options {
+                    next: Some(TimeDelta::max_value()),
+                    now: Some(now),
+                    ..Default::default()
+                },
+                [false, true, true, true, true],
+            ),
+            (
+                Options {
+                    from: Some(datetime("2024-01-01T11:00:00+00:00")),
+                    until: Some(datetime("2024-01-01T20:00:00+00:00")),
+                    ..Default::default()
+                },
+                [false, true, false, false, true],
+            ),
+        ];
+        for (options, expected) in tests {
+            let time_window = TimeWindow::new(&options);
+            let accepted = [ended, airing, today, tomorrow, now_without_length]
+                .map(|(start, stop)| time_window.accept(start, stop));
+            assert_eq!(accepted, expected, "for {time_window:?}");
+        }
+    }
+
+    #[test]
+    fn test_airing_filter() {
+        let new = Programme {
+            new: true,
+            ..Default::default()
+        };
+        let premiere = Programme {
+            premiere: Some(ValueAndLang {
+                value: String::new(),
+                lang: None,
+            }),
+            ..Default::default()
+        };
+        let repeat = Programme {
+            previously_shown: Some(PreviouslyShown::default()),
+            ..Default::default()
+        };
+        let unmarked = Programme::default();
+
+        let tests = [
+            (Options::default(), [true, true, true, true]),
+            (
+                Options {
+                    only_new: true,
+                    ..Default::default()
+                },
+                [true, true, false, false],
+            ),
+            (
+                Options {
+                    only_premieres: true,
+                    ..Default::default()
+                },
+                [false, true, false, false],
+            ),
+            (
+                Options {
+                    drop_repeats: true,
+                    ..Default::default()
+                },
+                [true, true, false, true],
+            ),
+        ];
+        for (options, expected) in tests {
+            let airing = AiringFilter::new(&options);
+            let accepted = [&new, &premiere, &repeat, &unmarked].map(|p| airing.accept(p));
+            assert_eq!(accepted, expected, "for {airing:?}");
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let datetime = |datetime| DateTime::parse_from_rfc3339(datetime).unwrap();
+
+        // Channel and start time
+        let programmes = [
+            ("a", datetime("2024-01-01T20:00:00+00:00")),
+            ("a", datetime("2024-01-01T21:00:00+00:00")),
+            ("b", datetime("2024-01-01T22:00:00+00:00")),
+            ("a", datetime("2024-01-02T20:00:00+00:00")),
+            ("b", datetime("2024-01-03T00:30:00+01:00")),
+        ];
+
+        let tests = [
+            (Options::default(), [true, true, true, true, true]),
+            (
+                Options {
+                    max_items: Some(3),
+                    ..Default::default()
+                },
+                [true, true, true, false, false],
+            ),
+            (
+                Options {
+                    max_items_per_channel: Some(1),
+                    ..Default::default()
+                },
+                [true, false, true, false, false],
+            ),
+            (
+                Options {
+                    max_days: Some(2),
+                    ..Default::default()
+                },
+                [true, true, true, true, false],
+            ),
+            (
+                Options {
+                    max_days: Some(100_000_000_000),
+                    ..Default::default()
+                },
+                [true, true, true, true, true],
+            ),
+            (
+                Options {
+                    max_items: Some(2),
+                    max_items_per_channel: Some(1),
+                    ..Default::default()
+                },
+                [true, false, true, false, false],
+            ),
+        ];
+        for (options, expected) in tests {
+            let mut limits = Limits::new(&options);
+            let accepted = programmes.map(|(channel, start)| limits.accept(channel, start));
+            assert_eq!(accepted, expected, "for {limits:?}");
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use xmltv::Programme;
+
+use crate::export::Options;
+use crate::xmltv::parse_from_str;
+
+/// Infers missing programme stop times from the start time of the next programme on the same channel.
+///
+/// Programmes with a stop time or a length are left as is. The next programme is the first programme
+/// on the same channel, in start time order, that starts later. If it starts more than
+/// [`Options::max_stop_gap`] later, then the stop time is not inferred.
+///
+/// Programmes with a start time that fails to parse are ignored.
+pub fn infer_stop_times(programmes: &mut [Programme], options: &Options) {
+    // Index into the programmes, per channel
+    let mut channel_programmes: HashMap<&str, Vec<_>> = HashMap::new();
+    for (i, programme) in programmes.iter().enumerate() {
+        let Ok(starttime_dt) = parse_from_str(
+            &programme.start,
+            options.xmltv_datetime_format,
+            options.input_timezone,
+        ) else {
+            continue;
+        };
+        channel_programmes
+            .entry(&programme.channel)
+            .or_default()
+            .push((starttime_dt, i));
+    }
+
+    let mut stoptimes = vec![];
+    for mut starttimes in channel_programmes.into_values() {
+        starttimes.sort();
+
+        for (j, &(starttime_dt, i)) in starttimes.iter().enumerate() {
+            let programme = &programmes[i];
+            if programme.stop.is_some() || programme.length.is_some() {
+                continue;
+            }
+
+            let Some(&(next_starttime_dt, next)) = starttimes[j + 1..]
+                .iter()
+                .find(|(next_starttime_dt, _)| *next_starttime_dt > starttime_dt)
+            else {
+                continue;
+            };
+            if options
+                .max_stop_gap
+                .is_some_and(|max_stop_gap| next_starttime_dt - starttime_dt > max_stop_gap)
+            {
+                continue;
+            }
+
+            stoptimes.push((i, programmes[next].start.clone()));
+        }
+    }
+
+    for (i, stoptime) in stoptimes {
+        programmes[i].stop = Some(stoptime);
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+    use pretty_assertions::assert_eq;
+    use xmltv::{Length, Units};
+
+    use super::*;
+
+    fn programme(channel: &str, start: &str, stop: Option<&str>) -> Programme {
+        Programme {
+            channel: channel.to_string(),
+            start: start.to_string(),
+            stop: stop.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test() {
+        let mut programmes = vec![
+            programme("a", "20240101120000 +0000", None),
+            programme("b", "20240101100000 +0000", None),
+            programme("a", "20240101100000 +0000", None),
+            programme("a", "20240101110000 +0000", Some("20240101113000 +0000")),
+            Programme {
+                length: Some(Length {
+                    length: 30,
+                    units: Units::Minutes,
+                }),
+                ..programme("b", "20240101110000 +0000", None)
+            },
+            programme("b", "20240101200000 +0000", None),
+            programme("c", "20240101100000 +0000", None),
+            programme("c", "20240101200000 +0000", None),
+        ];
+        let options = Options {
+            max_stop_gap: Some(TimeDelta::hours(6)),
+            ..Default::default()
+        };
+        infer_stop_times(&mut programmes, &options);
+
+        let stoptimes: Vec<_> = programmes.iter().map(|p| p.stop.as_deref()).collect();
+        assert_eq!(
+            stoptimes,
+            [
+                // Last programme on the channel
+                None,
+                Some("20240101110000 +0000"),
+                Some("20240101110000 +0000"),
+                // Has a stop time
+                Some("20240101113000 +0000"),
+                // Has a length
+                None,
+                // Last programme on the channel
+                None,
+                // The next programme starts more than 6 hours later
+                None,
+                None,
+            ]
+        );
+
+        infer_stop_times(&mut programmes, &Options::default());
+        assert_eq!(programmes[6].stop.as_deref(), Some("20240101200000 +0000"));
+    }
+}
@@ -0,0 +1,163 @@
+use chrono::{DateTime, Local};
+use std::fs::{self, File};
+use std::io::{self, BufRead, Cursor, Read};
+use std::path::Path;
+use xmltv::Tv;
+
+use crate::error::Error;
+use crate::xmltv::reader::Reader;
+
+/// Longest magic byte sequence of the supported compression formats.
+const MAGIC_LEN: usize = 6;
+
+/// Input compression format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Bzip2,
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression format from the magic bytes at the start of the input.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Returns the cargo feature that enables decompression of this format.
+    fn feature(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Bzip2 => "bzip2",
+            Compression::Gzip => "gzip",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// Opens an XMLTV file or else standard input if the file is `None` or "-".
+///
+/// Returns the reader and the file modification time or else the current time.
+pub(crate) fn open(file: Option<&Path>) -> Result<(Box<dyn Read>, DateTime<Local>), Error> {
+    let input: (Box<dyn Read>, _) = match file {
+        Some(file) if file != Path::new("-") => (
+            Box::new(File::open(file)?),
+            DateTime::<Local>::from(fs::metadata(file)?.modified()?),
+        ),
+        _ => (Box::new(io::stdin()), Local::now()),
+    };
+
+    Ok(input)
+}
+
+/// Reads a whole XMLTV TV listing from a file or else standard input if the file is `None` or "-".
+///
+/// Returns the XMLTV TV listing and the file modification time or else the current time.
+pub(crate) fn read(file: Option<&Path>) -> Result<(Tv, DateTime<Local>), Error> {
+    let (reader, modified) = open(file)?;
+    let listing = Reader::new(decompress(io::BufReader::new(reader))?).read_listing()?;
+
+    Ok((listing, modified))
+}
+
+/// Returns a reader that decompresses the input, if it is compressed.
+///
+/// The compression format is detected from the magic bytes at the start of the input.
+pub fn decompress<'a>(mut reader: impl BufRead + 'a) -> Result<Box<dyn BufRead + 'a>, Error> {
+    let mut magic = [0; MAGIC_LEN];
+    let mut len = 0;
+    while len < MAGIC_LEN {
+        match reader.read(&mut magic[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    let compression = Compression::detect(&magic[..len]);
+
+    // Put back the magic bytes
+    let reader = Cursor::new(magic[..len].to_vec()).chain(reader);
+
+    let reader: Box<dyn BufRead> = match compression {
+        Compression::None => Box::new(reader),
+
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => Box::new(io::BufReader::new(bzip2::bufread::MultiBzDecoder::new(
+            reader,
+        ))),
+
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Box::new(io::BufReader::new(flate2::bufread::MultiGzDecoder::new(
+            reader,
+        ))),
+
+        #[cfg(feature = "xz")]
+        Compression::Xz => Box::new(io::BufReader::new(
+            xz2::bufread::XzDecoder::new_multi_decoder(reader),
+        )),
+
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Box::new(io::BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+
+        #[allow(unreachable_patterns)]
+        _ => return Err(Error::UnsupportedCompression(compression.feature())),
+    };
+
+    Ok(reader)
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test() {
+        let expected = fs::read_to_string("tests/input/simple.xml").unwrap();
+
+        for (input_file, compression) in [
+            ("tests/input/simple.xml", Compression::None),
+            ("tests/input/simple.xml.bz2", Compression::Bzip2),
+            ("tests/input/simple.xml.gz", Compression::Gzip),
+            ("tests/input/simple.xml.xz", Compression::Xz),
+            ("tests/input/simple.xml.zst", Compression::Zstd),
+        ] {
+            let input = fs::read(input_file).unwrap();
+            assert_eq!(
+                Compression::detect(&input),
+                compression,
+                "for input file {input_file}"
+            );
+
+            let result =
+                decompress(input.as_slice()).and_then(|reader| Ok(io::read_to_string(reader)?));
+            match result {
+                Ok(output) => assert_eq!(output, expected, "for input file {input_file}"),
+                Err(Error::UnsupportedCompression(feature)) => {
+                    assert_eq!(
+                        feature,
+                        compression.feature(),
+                        "for input file {input_file}"
+                    )
+                }
+                Err(err) => panic!("for input file {input_file}: {err}"),
+            }
+        }
+    }
+}
@@ -23,7 +23,40 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! Or export from a reader, a string or an already parsed XMLTV TV listing:
+//!
+//! ```
+//! use chrono::Local;
+//! use std::fs::File;
+//! use std::io;
+//! use xmltv2rss::error::Result;
+//! use xmltv2rss::export::{atom, Options};
+//!
+//! fn print() -> Result<()> {
+//!     let options = Options::default();
+//!
+//!     let reader = File::open("./tests/input/simple.xml")?;
+//!     let feed = atom::export_from_reader("Title", "https://example.com/", None,
+//!                                         Some(Local::now()), &options, reader)?;
+//!
+//!     feed.write_to(io::stdout())?;
+//!
+//!     Ok(())
+//! }
+//! ```
 
+pub mod badge;
+pub mod dedup;
 pub mod error;
 pub mod export;
+pub mod filter;
+pub mod infer;
+pub mod input;
+pub mod merge;
+pub mod now;
+pub mod query;
+pub mod sort;
+pub mod split;
+pub mod watchlist;
 pub mod xmltv;
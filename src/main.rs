@@ -2,28 +2,117 @@
 //!
 //! [...more][`Args`]
 
-use atom_syndication::WriteConfig;
-use clap::{crate_version, Parser, ValueEnum};
-use std::io::{self, Write};
+use ::rss::{Channel, Image};
+use atom_syndication::{Feed, WriteConfig};
+use chrono::{
+    DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone,
+};
+use chrono_tz::Tz;
+use clap::{crate_version, CommandFactory, Parser, Subcommand, ValueEnum};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use xmltv::Tv;
 
-mod error;
-mod export;
-mod xmltv;
-
-use error::Result;
-use export::{atom, rss, Options, OptionsBuilder};
-use export::{DEFAULT_FEED_CHANNEL_TITLE, DEFAULT_FEED_DATE_FORMAT, DEFAULT_FEED_TIME_FORMAT};
-use xmltv::{DEFAULT_XMLTV_DATETIME_FORMAT, DEFAULT_XMLTV_DATETIME_FORMAT_UTC};
+use xmltv2rss::badge::BadgePlacement;
+use xmltv2rss::dedup::DedupAiring;
+use xmltv2rss::error::{Error, Result};
+use xmltv2rss::export::csv::{TableColumn, TableFormat};
+use xmltv2rss::export::html::HtmlView;
+use xmltv2rss::export::{
+    atom, csv, html, ics, json, opml, rss, Options, OptionsBuilder, Timezone, Warning,
+};
+use xmltv2rss::export::{
+    DEFAULT_FEED_CHANNEL_TITLE, DEFAULT_FEED_DATE_FORMAT, DEFAULT_FEED_TIME_FORMAT,
+};
+use xmltv2rss::filter::ChannelPattern;
+use xmltv2rss::merge::{self, MergePolicy};
+use xmltv2rss::now;
+use xmltv2rss::query::Query;
+use xmltv2rss::sort::SortOrder;
+use xmltv2rss::split::{self, SplitBy};
+use xmltv2rss::watchlist::Watchlist;
+use xmltv2rss::xmltv::DEFAULT_XMLTV_DATETIME_FORMAT;
 
 pub const DEFAULT_XML_INDENT: u8 = 2;
 
-#[derive(Clone, Default, Debug, ValueEnum)]
+/// Terminal width, if it cannot be determined.
+pub const DEFAULT_TERMINAL_WIDTH: usize = 80;
+
+/// Maximum duration of a duration argument, in days: about 100 years.
+const MAX_DURATION_DAYS: i64 = 36_525;
+
+// Exit codes, from sysexits.h
+
+/// Invalid command line arguments
+const EX_USAGE: u8 = 64;
+
+/// Invalid XMLTV input
+const EX_DATAERR: u8 = 65;
+
+/// Input file not found or not readable
+const EX_NOINPUT: u8 = 66;
+
+/// Input compression format not supported by this build
+const EX_UNAVAILABLE: u8 = 69;
+
+/// Internal error
+const EX_SOFTWARE: u8 = 70;
+
+/// Input or output error
+const EX_IOERR: u8 = 74;
+
+/// Invalid configuration file
+const EX_CONFIG: u8 = 78;
+
+#[derive(Clone, Default, Debug, PartialEq, ValueEnum)]
 enum FeedType {
     Atom,
 
+    /// Comma-separated values, with a header row. See --csv-column
+    Csv,
+
+    /// HTML TV guide page. See --html-view
+    Html,
+
+    /// iCalendar, with an event per programme
+    Ics,
+
+    /// JSON Feed 1.1
+    Json,
+
+    /// OPML 2.0 list of the feeds of --split-by channel. See --opml-base-url
+    Opml,
+
     /// Rss 2.0
     #[default]
     Rss,
+
+    /// Tab-separated values, with a header row. See --csv-column
+    Tsv,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the programme currently airing, with its progress, and the next programme of each channel.
+    /// Options such as --channel, --exclude-channel and --output-timezone apply.
+    Now(NowArgs),
+}
+
+#[derive(clap::Args)]
+struct NowArgs {
+    /// The current time. Defaults to the system time.
+    /// Examples: "2024-01-01 18:00" (local time), "2024-01-01T18:00:00+01:00".
+    #[arg(long, value_parser = parse_datetime)]
+    at: Option<DateTime<FixedOffset>>,
+
+    /// Width of the output in columns. Defaults to the terminal width.
+    #[arg(long)]
+    width: Option<usize>,
+
+    /// Read XMLTV files or from standard input if '-'. Multiple files are merged.
+    files: Vec<String>,
 }
 
 /// Generate an RSS or Atom feed from an XMLTV TV listing. Print the result to standard output.
@@ -33,6 +122,58 @@ enum FeedType {
 #[derive(Parser)]
 #[command(version = crate_version!())]
 struct Args {
+    /// Show the programme badges "NEW", "PREMIERE", "LAST CHANCE" and "Repeat of <date>"
+    /// in the item title or description.
+    #[arg(long, value_enum)]
+    badges: Option<BadgePlacement>,
+
+    /// Export only channels that match the pattern, and their programmes. Can be repeated.
+    /// The pattern is a case-insensitive glob pattern, matched against the XMLTV channel id and display names.
+    /// Prefix "id:" or "name:" to match only the channel id or display names.
+    /// Prefix "regex:" (after "id:" or "name:", if any) for a regular expression.
+    /// Examples: "bbc1.bbc.co.uk", "BBC *", "name:regex:^NPO [12]$".
+    #[arg(long = "channel", value_name = "PATTERN", global = true)]
+    channels: Vec<ChannelPattern>,
+
+    /// Column of the CSV or TSV output. Can be repeated, or separated by ','. Default: all columns.
+    #[arg(
+        long = "csv-column",
+        value_delimiter = ',',
+        value_enum,
+        value_name = "COLUMN"
+    )]
+    csv_columns: Vec<TableColumn>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Collapse repeated airings of the same episode into one item that lists every airing.
+    /// An episode is identified by its title with its "dd_progid", "xmltv_ns" or "onscreen" episode number,
+    /// or else with its sub-title and description. Reads the whole TV listing into memory.
+    #[arg(long)]
+    dedup: bool,
+
+    /// Airing of a deduplicated episode that supplies the item publication date and GUID.
+    #[arg(long, default_value_t, value_enum)]
+    dedup_airing: DedupAiring,
+
+    /// Duration of programmes without a stop time or length. Defaults to zero. Example: "30m".
+    #[arg(long, value_parser = parse_duration, global = true)]
+    default_duration: Option<TimeDelta>,
+
+    /// Do not export programmes that stopped before now.
+    #[arg(long)]
+    drop_ended: bool,
+
+    /// Do not export repeats: programmes marked previously shown.
+    #[arg(long)]
+    drop_repeats: bool,
+
+    /// Do not export channels that match the pattern, and their programmes. Can be repeated.
+    /// See --channel.
+    #[arg(long = "exclude-channel", value_name = "PATTERN", global = true)]
+    exclude_channels: Vec<ChannelPattern>,
+
     /// Output feed date format. Examples: "%%Y-%%m-%%d", "%%a %%d %%B, %%Y", "%%x".
     #[arg(long, short = 'd', default_value = DEFAULT_FEED_DATE_FORMAT)]
     feed_date_format: String,
@@ -46,7 +187,7 @@ struct Args {
     feed_indent: u8,
 
     /// Output feed language.
-    #[arg(long)]
+    #[arg(long, global = true)]
     feed_language: Option<String>,
 
     /// Output feed URL.
@@ -54,7 +195,7 @@ struct Args {
     feed_link: String,
 
     /// Output feed time format. Examples: "%%H:%%M", "%%I:%%M %%p", "%%X".
-    #[arg(long, short = 't', default_value = DEFAULT_FEED_TIME_FORMAT)]
+    #[arg(long, short = 't', default_value = DEFAULT_FEED_TIME_FORMAT, global = true)]
     feed_time_format: String,
 
     /// Output feed title.
@@ -65,20 +206,154 @@ struct Args {
     #[arg(long, default_value_t, value_enum)]
     feed_type: FeedType,
 
-    // #[arg(long, default_value = DEFAULT_XMLTV_DATETIME_FORMAT,
-    //       help = concatcp!("XMLTV date and time format\n[default fallback: \"", DEFAULT_XMLTV_DATETIME_FORMAT_UTC, "\"]"))]
-    #[arg(long, default_value = DEFAULT_XMLTV_DATETIME_FORMAT,
-          help = format!("XMLTV date and time format\n[default fallback: {DEFAULT_XMLTV_DATETIME_FORMAT_UTC:?}]"))]
+    /// Export only programmes that stop after this date and time.
+    /// Examples: "2024-01-01", "2024-01-01 18:00" (local time), "2024-01-01T18:00:00+01:00".
+    #[arg(long, value_parser = parse_datetime)]
+    from: Option<DateTime<FixedOffset>>,
+
+    /// View of the HTML TV guide.
+    #[arg(long, default_value_t, value_enum)]
+    html_view: HtmlView,
+
+    /// Infer missing programme stop times from the start time of the next programme on the same channel.
+    /// Reads the whole TV listing into memory.
+    #[arg(long, global = true)]
+    infer_stop_times: bool,
+
+    /// Timezone of XMLTV dates and times without a UTC offset, instead of UTC. Example: "Europe/Amsterdam".
+    #[arg(long, global = true)]
+    input_timezone: Option<Tz>,
+
+    /// Skip programmes that fail to parse and print a warning, instead of failing.
+    #[arg(long, global = true)]
+    lenient: bool,
+
+    /// Export only programmes that start within this number of days, from the start day of the earliest
+    /// exported programme. Applied after filtering and sorting. Reads the whole TV listing into memory.
+    #[arg(long, value_name = "DAYS")]
+    max_days: Option<u64>,
+
+    /// Export at most this number of programmes. Applied after filtering and sorting.
+    #[arg(long, value_name = "N")]
+    max_items: Option<usize>,
+
+    /// Export at most this number of programmes per channel. Applied after filtering and sorting.
+    #[arg(long, value_name = "N")]
+    max_items_per_channel: Option<usize>,
+
+    /// Maximum time from the start of a programme to the start of the next programme,
+    /// to infer the stop time from. Example: "6h".
+    #[arg(long, value_parser = parse_duration, global = true)]
+    max_stop_gap: Option<TimeDelta>,
+
+    /// Conflict policy for overlapping programmes on the same channel from different XMLTV files.
+    /// Overlap is by the stop times in the files, not by inferred stop times.
+    #[arg(long, default_value_t, value_enum)]
+    merge_policy: MergePolicy,
+
+    /// Export only programmes that air from now until now plus this duration. Example: "24h".
+    #[arg(long, value_parser = parse_duration)]
+    next: Option<TimeDelta>,
+
+    /// Export only first airings: programmes marked new or premiere.
+    #[arg(long)]
+    only_new: bool,
+
+    /// Export only programmes marked premiere.
+    #[arg(long)]
+    only_premieres: bool,
+
+    /// Base URL of the feeds in the OPML output, followed by the feed file names of --split-by channel.
+    /// Example: "https://example.com/feeds/".
+    #[arg(long, default_value = "", value_name = "URL")]
+    opml_base_url: String,
+
+    /// Group the feeds in the OPML output by the categories of the channel programmes.
+    #[arg(long)]
+    opml_group_by_category: bool,
+
+    /// Directory to write the feeds to, with --split-by.
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// Timezone of the output feed dates and times: "local" or an IANA timezone. Example: "Europe/Berlin".
+    /// Defaults to UTC for programme dates and times.
+    #[arg(long, global = true)]
+    output_timezone: Option<Timezone>,
+
+    /// Sort the feed items: by start time, by channel id then start time, by title then start time,
+    /// or by start time with the latest first. Defaults to the input order.
+    /// Reads the whole TV listing into memory.
+    #[arg(long, value_enum)]
+    sort: Option<SortOrder>,
+
+    /// Write one feed per XMLTV channel to the output directory, instead of one feed to standard output.
+    /// The file names are derived from the channel ids. Each feed has the channel display name as title
+    /// and the channel icon as image. An "index.html" file links to all the feeds,
+    /// and an "index.opml" file lists all the feeds. See --opml-base-url.
+    #[arg(long, value_enum, requires = "output_dir")]
+    split_by: Option<SplitBy>,
+
+    /// Export only programmes that start before this date and time. See --from.
+    #[arg(long, value_parser = parse_datetime)]
+    until: Option<DateTime<FixedOffset>>,
+
+    /// Export only programmes that match a rule in the watchlist TOML file, tagged with the matching rules.
+    /// A rule has a "name" and matches any of the fields "title", "sub-title", "description", "category",
+    /// "channel" and "credits" by case-insensitive substring, or by regular expression with prefix "regex:",
+    /// and matches the field "time" by start time of day range "hh:mm-hh:mm".
+    #[arg(long, value_name = "FILE")]
+    watchlist: Option<PathBuf>,
+
+    /// Export only programmes that match the query expression.
+    /// Example: 'category == "Film" and duration > 90m and not previously_shown and channel in ["bbc1", "bbc2"]'.
+    /// Operators: or, and, not, ==, !=, <, <=, >, >=, in [...], contains, ~ (regular expression).
+    /// Fields: title, sub_title, description, category, keyword, channel, rating, credits, hour, star_rating,
+    /// duration, weekday, new, premiere, last_chance, previously_shown.
+    #[arg(long = "where", value_name = "EXPRESSION")]
+    query: Option<Query>,
+
+    #[arg(long, default_value = DEFAULT_XMLTV_DATETIME_FORMAT, global = true,
+          help = "XMLTV date and time format\n[default fallback: XMLTV DTD format \"YYYYMMDDhhmmss +zzzz\" or an initial substring]")]
     xmltv_datetime_format: String,
 
-    /// Read XMLTV file or from standard input if '-'.
-    file: Option<String>,
+    /// Read XMLTV files or from standard input if '-'. The input may be bzip2, gzip, xz or zstd compressed.
+    /// Multiple files, and the ".xml" files in a directory (optionally compressed, for example, ".xml.gz"),
+    /// are merged into one feed.
+    files: Vec<String>,
 }
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     let args = Args::parse();
 
-    export(&args)
+    match export(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
+
+/// Returns the exit code for the category of the error.
+fn exit_code(err: &Error) -> u8 {
+    match err.root() {
+        Error::Io(err)
+            if matches!(
+                err.kind(),
+                ErrorKind::NotFound | ErrorKind::PermissionDenied
+            ) =>
+        {
+            EX_NOINPUT
+        }
+        Error::Io(_) | Error::Xml(quick_xml::Error::Io(_)) => EX_IOERR,
+        Error::De(_) | Error::InvalidDatetime(..) | Error::Parse(_) | Error::Xml(_) => EX_DATAERR,
+        Error::Atom(_) | Error::Csv(_) | Error::Json(_) | Error::Rss(_) => EX_IOERR,
+        Error::DuplicateStdin => EX_USAGE,
+        Error::Toml(_) => EX_CONFIG,
+        Error::UnsupportedCompression(_) => EX_UNAVAILABLE,
+        Error::OptionsBuilder(_) | Error::Position { .. } | Error::Programme { .. } => EX_SOFTWARE,
+    }
 }
 
 //
@@ -91,25 +366,174 @@ fn export(args: &Args) -> Result<()> {
             options.language(language.as_str());
         }
     }
+    if let Some(input_timezone) = args.input_timezone {
+        options.input_timezone(input_timezone);
+    }
+    options.include_channels(&args.channels);
+    options.exclude_channels(&args.exclude_channels);
+    if let Some(from) = args.from {
+        options.from(from);
+    }
+    if let Some(until) = args.until {
+        options.until(until);
+    }
+    if let Some(next) = args.next {
+        options.next(next);
+    }
+    options.drop_ended(args.drop_ended);
+    let watchlist = args
+        .watchlist
+        .as_ref()
+        .map(Watchlist::from_file)
+        .transpose()?;
+    if let Some(watchlist) = &watchlist {
+        options.watchlist(watchlist);
+    }
+    if let Some(query) = &args.query {
+        options.query(query);
+    }
+    options.only_new(args.only_new);
+    options.only_premieres(args.only_premieres);
+    options.drop_repeats(args.drop_repeats);
+    if let Some(badges) = args.badges {
+        options.badges(badges);
+    }
+    options.dedup(args.dedup);
+    options.dedup_airing(args.dedup_airing);
+    if let Some(sort) = args.sort {
+        options.sort(sort);
+    }
+    if let Some(max_items) = args.max_items {
+        options.max_items(max_items);
+    }
+    if let Some(max_items_per_channel) = args.max_items_per_channel {
+        options.max_items_per_channel(max_items_per_channel);
+    }
+    if let Some(max_days) = args.max_days {
+        options.max_days(max_days);
+    }
+    options.infer_stop_times(args.infer_stop_times);
+    if let Some(max_stop_gap) = args.max_stop_gap {
+        options.max_stop_gap(max_stop_gap);
+    }
+    if let Some(default_duration) = args.default_duration {
+        options.default_duration(default_duration);
+    }
+    options.merge_policy(args.merge_policy);
+    let print_warning = |warning: &Warning| eprintln!("Warning: {warning}");
+    if args.lenient {
+        options.lenient(true).warning_handler(&print_warning);
+    }
+    if let Some(output_timezone) = args.output_timezone {
+        options.output_timezone(output_timezone);
+    }
+    if let Some(Command::Now(NowArgs { at: Some(at), .. })) = &args.command {
+        options.now(*at);
+    }
     let options = options.build()?;
 
+    if let Some(Command::Now(now_args)) = &args.command {
+        return print_now_next(now_args, &options);
+    }
+
+    if let (Some(SplitBy::Channel), Some(output_dir)) = (args.split_by, &args.output_dir) {
+        if !matches!(args.feed_type, FeedType::Atom | FeedType::Rss) {
+            Args::command()
+                .error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "--split-by requires an RSS or Atom feed type",
+                )
+                .exit();
+        }
+        return export_split_by_channel(args, &options, output_dir);
+    }
+
     let mut writer = io::stdout();
 
     match args.feed_type {
         FeedType::Atom => export_to_atom(args, &options, &mut writer),
+        FeedType::Csv => export_to_csv(args, TableFormat::Csv, &options, &mut writer),
+        FeedType::Html => export_to_html(args, &options, &mut writer),
+        FeedType::Ics => export_to_ics(args, &options, &mut writer),
+        FeedType::Json => export_to_json(args, &options, &mut writer),
+        FeedType::Opml => export_to_opml(args, &options, &mut writer),
         FeedType::Rss => export_to_rss(args, &options, &mut writer),
+        FeedType::Tsv => export_to_csv(args, TableFormat::Tsv, &options, &mut writer),
+    }
+}
+
+/// Parses an RFC 3339 date and time, or a date with an optional time in the local timezone.
+fn parse_datetime(datetime: &str) -> std::result::Result<DateTime<FixedOffset>, String> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(datetime) {
+        return Ok(datetime);
+    }
+
+    let naive_datetime = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(datetime, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(datetime, "%Y-%m-%d")
+            .ok()
+            .map(|date| date.and_time(NaiveTime::MIN))
+    })
+    .ok_or_else(|| format!("invalid date and time {datetime:?}"))?;
+    Local
+        .from_local_datetime(&naive_datetime)
+        .earliest()
+        .map(|datetime| datetime.fixed_offset())
+        .ok_or_else(|| format!("non-existent local date and time {datetime:?}"))
+}
+
+/// Parses a duration. Examples: "90m", "1h 30m".
+fn parse_duration(duration: &str) -> std::result::Result<TimeDelta, String> {
+    let duration = humantime::parse_duration(duration).map_err(|err| err.to_string())?;
+    TimeDelta::from_std(duration)
+        .ok()
+        .filter(|duration| *duration <= TimeDelta::days(MAX_DURATION_DAYS))
+        .ok_or_else(|| "duration too large, at most 100 years".to_owned())
+}
+
+/// Reads and merges the XMLTV files, if there is more than one file or a directory.
+/// Otherwise, the XMLTV file will be streamed.
+fn read(args: &Args, options: &Options) -> Result<Option<(Tv, DateTime<Local>)>> {
+    match args.files.as_slice() {
+        [] => Ok(None),
+        [file] if !Path::new(file).is_dir() => Ok(None),
+        files => {
+            let files: Vec<_> = files.iter().map(String::as_str).collect();
+            merge::read(&files, options).map(Some)
+        }
     }
 }
 
 fn export_to_atom(args: &Args, options: &Options, writer: &mut impl Write) -> Result<()> {
-    let feed = atom::export(
-        &args.feed_title,
-        &args.feed_link,
-        args.feed_description.as_deref(),
-        options,
-        args.file.as_deref(),
-    )?;
+    let feed = match read(args, options)? {
+        Some((listing, updated)) => atom::export_from_tv(
+            &args.feed_title,
+            &args.feed_link,
+            args.feed_description.as_deref(),
+            Some(updated),
+            options,
+            &listing,
+        )?,
+        None => atom::export(
+            &args.feed_title,
+            &args.feed_link,
+            args.feed_description.as_deref(),
+            options,
+            args.files.first().map(String::as_str),
+        )?,
+    };
 
+    write_atom(args, &feed, writer)
+}
+
+fn write_atom(args: &Args, feed: &Feed, writer: &mut impl Write) -> Result<()> {
     let feed_indent = args.feed_indent;
     if feed_indent > 0 {
         let config = WriteConfig {
@@ -124,15 +548,132 @@ fn export_to_atom(args: &Args, options: &Options, writer: &mut impl Write) -> Re
     Ok(())
 }
 
+fn export_to_csv(
+    args: &Args,
+    format: TableFormat,
+    options: &Options,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let table = match read(args, options)? {
+        Some((listing, _)) => csv::export_from_tv(format, &args.csv_columns, options, &listing)?,
+        None => csv::export(
+            format,
+            &args.csv_columns,
+            options,
+            args.files.first().map(String::as_str),
+        )?,
+    };
+
+    table.write_to(writer)
+}
+
+fn export_to_html(args: &Args, options: &Options, writer: &mut impl Write) -> Result<()> {
+    let guide = match read(args, options)? {
+        Some((listing, _)) => html::export_from_tv(
+            &args.feed_title,
+            args.feed_description.as_deref(),
+            args.html_view,
+            options,
+            &listing,
+        )?,
+        None => html::export(
+            &args.feed_title,
+            args.feed_description.as_deref(),
+            args.html_view,
+            options,
+            args.files.first().map(String::as_str),
+        )?,
+    };
+
+    guide.write_to(writer)
+}
+
+fn export_to_ics(args: &Args, options: &Options, writer: &mut impl Write) -> Result<()> {
+    let calendar = match read(args, options)? {
+        Some((listing, _)) => ics::export_from_tv(
+            &args.feed_title,
+            args.feed_description.as_deref(),
+            options,
+            &listing,
+        )?,
+        None => ics::export(
+            &args.feed_title,
+            args.feed_description.as_deref(),
+            options,
+            args.files.first().map(String::as_str),
+        )?,
+    };
+
+    calendar.write_to(writer)
+}
+
+fn export_to_json(args: &Args, options: &Options, writer: &mut impl Write) -> Result<()> {
+    let feed = match read(args, options)? {
+        Some((listing, _)) => json::export_from_tv(
+            &args.feed_title,
+            &args.feed_link,
+            args.feed_description.as_deref(),
+            options,
+            &listing,
+        )?,
+        None => json::export(
+            &args.feed_title,
+            &args.feed_link,
+            args.feed_description.as_deref(),
+            options,
+            args.files.first().map(String::as_str),
+        )?,
+    };
+
+    feed.write_to(writer, args.feed_indent.into())
+}
+
+fn export_to_opml(args: &Args, options: &Options, writer: &mut impl Write) -> Result<()> {
+    let opml = match read(args, options)? {
+        Some((listing, date_created)) => opml::export_from_tv(
+            &args.feed_title,
+            &args.opml_base_url,
+            args.opml_group_by_category,
+            Some(date_created),
+            options,
+            &listing,
+        )?,
+        None => opml::export(
+            &args.feed_title,
+            &args.opml_base_url,
+            args.opml_group_by_category,
+            options,
+            args.files.first().map(String::as_str),
+        )?,
+    };
+
+    opml.write_to(writer, args.feed_indent.into())
+}
+
 fn export_to_rss(args: &Args, options: &Options, writer: &mut impl Write) -> Result<()> {
-    let channel = rss::export(
-        &args.feed_title,
-        &args.feed_link,
-        args.feed_description.as_deref(),
-        options,
-        args.file.as_deref(),
-    )?;
+    let channel = match read(args, options)? {
+        Some((listing, pub_date)) => rss::export_from_tv(
+            &args.feed_title,
+            &args.feed_link,
+            args.feed_description.as_deref(),
+            Some(pub_date),
+            Some(Local::now()),
+            options,
+            &listing,
+        )?,
+        None => rss::export(
+            &args.feed_title,
+            &args.feed_link,
+            args.feed_description.as_deref(),
+            options,
+            args.files.first().map(String::as_str),
+        )?,
+    };
+
+    write_rss(args, &channel, writer)
+}
 
+fn write_rss(args: &Args, channel: &Channel, writer: &mut impl Write) -> Result<()> {
     let feed_indent = args.feed_indent;
     if feed_indent > 0 {
         channel.pretty_write_to(writer, b' ', feed_indent.into())?;
@@ -142,3 +683,97 @@ fn export_to_rss(args: &Args, options: &Options, writer: &mut impl Write) -> Res
 
     Ok(())
 }
+
+/// Prints the programme currently airing and the next programme of each channel.
+fn print_now_next(args: &NowArgs, options: &Options) -> Result<()> {
+    let files = if args.files.is_empty() {
+        vec!["-"]
+    } else {
+        args.files.iter().map(String::as_str).collect()
+    };
+    let (listing, _) = merge::read(&files, options)?;
+
+    let channels = now::now_next(&listing, options)?;
+    let width = args
+        .width
+        .or_else(|| termize::dimensions_stdout().map(|(width, _)| width))
+        .unwrap_or(DEFAULT_TERMINAL_WIDTH);
+
+    now::write_table(io::stdout(), &channels, options, width)
+}
+
+/// Writes one feed per XMLTV channel to the output directory, and an index file.
+fn export_split_by_channel(args: &Args, options: &Options, output_dir: &Path) -> Result<()> {
+    let files = if args.files.is_empty() {
+        vec!["-"]
+    } else {
+        args.files.iter().map(String::as_str).collect()
+    };
+    let (listing, updated) = merge::read(&files, options)?;
+
+    fs::create_dir_all(output_dir)?;
+
+    let channel_listings = split::split_by_channel(&listing, options);
+    let mut feeds = vec![];
+    for channel_listing in &channel_listings {
+        let title = channel_listing.title(options.language);
+        let file_name = format!("{}.xml", channel_listing.file_stem);
+        let mut writer = BufWriter::new(File::create(output_dir.join(&file_name))?);
+
+        match args.feed_type {
+            FeedType::Atom => {
+                let mut feed = atom::export_from_tv(
+                    title,
+                    &args.feed_link,
+                    args.feed_description.as_deref(),
+                    Some(updated),
+                    options,
+                    &channel_listing.listing,
+                )?;
+                feed.set_logo(channel_listing.icon().map(str::to_string));
+                write_atom(args, &feed, &mut writer)?;
+            }
+            FeedType::Csv
+            | FeedType::Html
+            | FeedType::Ics
+            | FeedType::Json
+            | FeedType::Opml
+            | FeedType::Tsv => unreachable!("--split-by with a feed type other than RSS or Atom"),
+            FeedType::Rss => {
+                let mut channel = rss::export_from_tv(
+                    title,
+                    &args.feed_link,
+                    args.feed_description.as_deref(),
+                    Some(updated),
+                    Some(Local::now()),
+                    options,
+                    &channel_listing.listing,
+                )?;
+                channel.set_image(channel_listing.icon().map(|icon| Image {
+                    url: icon.to_string(),
+                    title: title.to_string(),
+                    link: args.feed_link.clone(),
+                    ..Default::default()
+                }));
+                write_rss(args, &channel, &mut writer)?;
+            }
+        }
+        writer.flush()?;
+
+        feeds.push((title.to_string(), file_name));
+    }
+
+    let writer = BufWriter::new(File::create(output_dir.join("index.html"))?);
+    split::write_index(writer, &args.feed_title, &feeds)?;
+
+    let opml = opml::export_from_channel_listings(
+        &args.feed_title,
+        &args.opml_base_url,
+        args.opml_group_by_category,
+        Some(updated),
+        options,
+        &channel_listings,
+    )?;
+    let writer = BufWriter::new(File::create(output_dir.join("index.opml"))?);
+    opml.write_to(writer, args.feed_indent.into())
+}
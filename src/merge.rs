@@ -0,0 +1,399 @@
+use chrono::{DateTime, FixedOffset, Local};
+use clap::ValueEnum;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use xmltv::{Programme, Tv};
+
+use crate::error::{Error, ProgrammeContext};
+use crate::export::{skip_programme, Options};
+use crate::filter::ChannelFilter;
+use crate::input;
+use crate::xmltv::parse_programme_times;
+
+/// File name extensions of compressed XMLTV files in a directory, after ".xml". See [`read`].
+const COMPRESSION_EXTENSIONS: [&str; 4] = ["bz2", "gz", "xz", "zst"];
+
+/// Conflict policy for overlapping programmes on the same channel from different XMLTV inputs.
+///
+/// Programmes overlap by their stop times in the inputs, or else by their length or
+/// [`Options::default_duration`]. Stop times inferred with [`Options::infer_stop_times`] are not used:
+/// a programme without stop time, length or default duration overlaps only programmes that start at the same time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum MergePolicy {
+    /// Keep the programme of the first input
+    #[default]
+    FirstWins,
+
+    /// Keep the programme of the last input
+    LastWins,
+
+    /// Keep the programme with the longest description
+    LongestDescription,
+}
+
+/// Reads and merges XMLTV TV listings from files and directories, or from standard input if '-'.
+///
+/// A directory is read as the XMLTV files in it, in file name order: the files with extension ".xml",
+/// optionally followed by a compression extension, for example, "listing.xml" and "listing.xml.gz".
+/// Standard input can be read only once.
+///
+/// Returns the merged XMLTV TV listing and the latest file modification time.
+pub fn read(paths: &[&str], options: &Options) -> Result<(Tv, DateTime<Local>), Error> {
+    if paths.iter().filter(|path| **path == "-").count() > 1 {
+        return Err(Error::DuplicateStdin);
+    }
+
+    let mut files = vec![];
+    for path in paths {
+        if *path != "-" && Path::new(path).is_dir() {
+            let mut dir_files = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            dir_files.retain(|file| file.is_file() && is_xmltv_file_name(file));
+            dir_files.sort();
+            files.extend(dir_files);
+        } else {
+            files.push(PathBuf::from(path));
+        }
+    }
+
+    let mut listings = vec![];
+    let mut pub_date = None;
+    for file in &files {
+        let (listing, modified) = input::read(Some(file))?;
+        listings.push(listing);
+        pub_date = pub_date.max(Some(modified));
+    }
+
+    let listing = merge(listings, options)?;
+
+    Ok((listing, pub_date.unwrap_or_else(Local::now)))
+}
+
+/// Returns true if the file name has extension ".xml", optionally followed by a compression extension.
+fn is_xmltv_file_name(path: &Path) -> bool {
+    let Some(file_name) = path.file_name().and_then(|file_name| file_name.to_str()) else {
+        return false;
+    };
+    let file_name = file_name.to_ascii_lowercase();
+    let file_name = file_name
+        .rsplit_once('.')
+        .filter(|(_, extension)| COMPRESSION_EXTENSIONS.contains(extension))
+        .map_or(file_name.as_str(), |(file_name, _)| file_name);
+
+    file_name.ends_with(".xml")
+}
+
+/// Merges XMLTV TV listings into one.
+///
+/// Channels are deduplicated by id. Overlapping programmes on the same channel from different listings
+/// are resolved with [`Options::merge_policy`].
+/// Programmes of channels excluded by [`Options::include_channels`] and [`Options::exclude_channels`]
+/// are dropped without being parsed.
+pub fn merge(listings: Vec<Tv>, options: &Options) -> Result<Tv, Error> {
+    // Match the channels as merged: the first channel with an id
+    let mut channel_filter = ChannelFilter::new(options);
+    let mut visited = HashSet::new();
+    for channel in listings.iter().flat_map(|listing| &listing.channels) {
+        if visited.insert(&channel.id) {
+            channel_filter.accept_channel(channel);
+        }
+    }
+
+    let mut listings = listings.into_iter();
+    let Some(mut merged) = listings.next() else {
+        return Ok(Tv::default());
+    };
+
+    let mut channel_ids: HashSet<_> = merged.channels.iter().map(|c| c.id.clone()).collect();
+
+    // Merged programmes, in input order. Removed programmes are set to None.
+    let mut programmes = vec![];
+    // Index into the merged programmes, per channel
+    let mut channel_programmes: HashMap<String, Vec<usize>> = HashMap::new();
+
+    for (index, programme) in merged.programmes.drain(..).enumerate() {
+        if !channel_filter.accept_channel_id(&programme.channel) {
+            continue;
+        }
+        let Some(entry) = Entry::parse(programme, 0, index, options)? else {
+            continue;
+        };
+        channel_programmes
+            .entry(entry.programme.channel.clone())
+            .or_default()
+            .push(programmes.len());
+        programmes.push(Some(entry));
+    }
+
+    for (source, listing) in listings.enumerate().map(|(i, l)| (i + 1, l)) {
+        for channel in listing.channels {
+            if channel_ids.insert(channel.id.clone()) {
+                merged.channels.push(channel);
+            }
+        }
+
+        for (index, programme) in listing.programmes.into_iter().enumerate() {
+            if !channel_filter.accept_channel_id(&programme.channel) {
+                continue;
+            }
+            let Some(entry) = Entry::parse(programme, source, index, options)? else {
+                continue;
+            };
+            let indices = channel_programmes
+                .entry(entry.programme.channel.clone())
+                .or_default();
+
+            let overlapping: Vec<_> = indices
+                .iter()
+                .copied()
+                .filter(|&i| {
+                    programmes[i]
+                        .as_ref()
+                        .is_some_and(|other| other.source != source && other.overlaps(&entry))
+                })
+                .collect();
+
+            let keep = match options.merge_policy {
+                MergePolicy::FirstWins => overlapping.is_empty(),
+                MergePolicy::LastWins => true,
+                MergePolicy::LongestDescription => overlapping.iter().all(|&i| {
+                    programmes[i]
+                        .as_ref()
+                        .is_some_and(|other| entry.description_len() > other.description_len())
+                }),
+            };
+            if !keep {
+                continue;
+            }
+
+            for i in overlapping {
+                programmes[i] = None;
+            }
+            indices.retain(|&i| programmes[i].is_some());
+            indices.push(programmes.len());
+            programmes.push(Some(entry));
+        }
+    }
+
+    merged.programmes = programmes
+        .into_iter()
+        .flatten()
+        .map(|entry| entry.programme)
+        .collect();
+
+    Ok(merged)
+}
+
+/// Merged programme.
+struct Entry {
+    programme: Programme,
+    source: usize,
+    starttime_dt: DateTime<FixedOffset>,
+    stoptime_dt: DateTime<FixedOffset>,
+}
+
+impl Entry {
+    /// Returns the merged programme or else `None` if it is skipped in lenient mode.
+    ///
+    /// The index is the index of the programme in its source listing.
+    fn parse(
+        programme: Programme,
+        source: usize,
+        index: usize,
+        options: &Options,
+    ) -> Result<Option<Self>, Error> {
+        let (starttime_dt, stoptime_dt) = match parse_programme_times(&programme, options) {
+            Ok(times) => times,
+            Err(err) => {
+                let context = ProgrammeContext::new(&programme, index, None);
+                return skip_programme(context, err, options).map(|_| None);
+            }
+        };
+
+        Ok(Some(Self {
+            programme,
+            source,
+            starttime_dt,
+            stoptime_dt,
+        }))
+    }
+
+    /// Returns true if the airtimes overlap. Programmes without length overlap if they start at the same time.
+    fn overlaps(&self, other: &Entry) -> bool {
+        if self.starttime_dt == other.starttime_dt {
+            return true;
+        }
+
+        self.starttime_dt < other.stoptime_dt && other.starttime_dt < self.stoptime_dt
+    }
+
+    /// Returns the length of the longest description.
+    fn description_len(&self) -> usize {
+        self.programme
+            .descriptions
+            .iter()
+            .map(|desc| desc.value.trim().chars().count())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use xmltv::{Channel, ValueAndLang};
+
+    use super::*;
+
+    fn listing(channels: &[&str], programmes: &[(&str, &str, &str, &str)]) -> Tv {
+        Tv {
+            channels: channels
+                .iter()
+                .map(|id| Channel {
+                    id: id.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            programmes: programmes
+                .iter()
+                .map(|(channel, start, stop, desc)| Programme {
+                    channel: channel.to_string(),
+                    start: start.to_string(),
+                    stop: Some(stop.to_string()),
+                    descriptions: vec![ValueAndLang {
+                        value: desc.to_string(),
+                        lang: None,
+                    }],
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn descriptions(listing: &Tv) -> Vec<&str> {
+        listing
+            .programmes
+            .iter()
+            .map(|p| p.descriptions[0].value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test() {
+        let first = listing(
+            &["a", "b"],
+            &[
+                (
+                    "a",
+                    "20240101100000 +0000",
+                    "20240101110000 +0000",
+                    "a1 first",
+                ),
+                (
+                    "a",
+                    "20240101110000 +0000",
+                    "20240101120000 +0000",
+                    "a2 first",
+                ),
+                (
+                    "b",
+                    "20240101100000 +0000",
+                    "20240101110000 +0000",
+                    "b1 first",
+                ),
+            ],
+        );
+        let last = listing(
+            &["b", "c"],
+            &[
+                (
+                    "a",
+                    "20240101103000 +0000",
+                    "20240101113000 +0000",
+                    "a1 last, longer",
+                ),
+                (
+                    "b",
+                    "20240101110000 +0000",
+                    "20240101120000 +0000",
+                    "b2 last",
+                ),
+                (
+                    "c",
+                    "20240101100000 +0000",
+                    "20240101110000 +0000",
+                    "c1 last",
+                ),
+            ],
+        );
+
+        const TESTS: [(MergePolicy, &[&str]); 3] = [
+            (
+                MergePolicy::FirstWins,
+                &["a1 first", "a2 first", "b1 first", "b2 last", "c1 last"],
+            ),
+            (
+                MergePolicy::LastWins,
+                &["b1 first", "a1 last, longer", "b2 last", "c1 last"],
+            ),
+            (
+                MergePolicy::LongestDescription,
+                &["b1 first", "a1 last, longer", "b2 last", "c1 last"],
+            ),
+        ];
+
+        for (merge_policy, expected) in TESTS {
+            let options = Options {
+                merge_policy,
+                ..Default::default()
+            };
+            let merged = merge(vec![first.clone(), last.clone()], &options).unwrap();
+
+            let channel_ids: Vec<_> = merged.channels.iter().map(|c| c.id.as_str()).collect();
+            assert_eq!(channel_ids, ["a", "b", "c"]);
+            assert_eq!(descriptions(&merged), expected, "for {merge_policy:?}");
+        }
+    }
+
+    #[test]
+    fn test_excluded_channel() {
+        let first = listing(
+            &["a"],
+            &[("a", "20240101100000 +0000", "20240101110000 +0000", "a1")],
+        );
+        let last = listing(&["b"], &[("b", "invalid", "20240101110000 +0000", "b1")]);
+
+        let exclude = ["b".parse().unwrap()];
+        let options = Options {
+            exclude_channels: &exclude,
+            ..Default::default()
+        };
+        let merged = merge(vec![first.clone(), last.clone()], &options).unwrap();
+        assert_eq!(descriptions(&merged), ["a1"]);
+
+        assert!(merge(vec![first, last], &Options::default()).is_err());
+    }
+
+    #[test]
+    fn test_read() {
+        let result = read(&["-", "tests/input", "-"], &Options::default());
+        assert!(matches!(result, Err(Error::DuplicateStdin)));
+
+        const TESTS: [(&str, bool); 6] = [
+            ("listing.xml", true),
+            ("dir/Listing.XML.gz", true),
+            ("listing.xml.zst", true),
+            ("listing.gz", false),
+            ("listing.xml.txt", false),
+            ("README", false),
+        ];
+        for (file, expected) in TESTS {
+            assert_eq!(is_xmltv_file_name(Path::new(file)), expected, "for {file}");
+        }
+    }
+}
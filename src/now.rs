@@ -0,0 +1,312 @@
+use chrono::{DateTime, FixedOffset};
+use std::borrow::Cow;
+use std::io::Write;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use xmltv::{Channel, Tv};
+
+use crate::error::{Error, ProgrammeContext};
+use crate::export::{channel_name, skip_programme, Options};
+use crate::filter::ChannelFilter;
+use crate::infer::infer_stop_times;
+use crate::xmltv::{find_value, parse_programme_times};
+
+/// Number of cells of the progress indicator.
+const PROGRESS_BAR_WIDTH: usize = 10;
+
+/// Programme currently airing and next programme of a channel.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NowNext {
+    pub channel_id: String,
+
+    /// Display name, or else the channel id
+    pub channel_name: String,
+
+    pub now: Option<NowProgramme>,
+    pub next: Option<NowProgramme>,
+}
+
+/// Programme of the now and next view.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NowProgramme {
+    pub title: String,
+
+    /// Start time, in the output timezone
+    pub start: DateTime<FixedOffset>,
+
+    /// Stop time, in the output timezone
+    pub stop: DateTime<FixedOffset>,
+}
+
+impl NowProgramme {
+    /// Returns the elapsed part of the programme at the time, from 0.0 to 1.0.
+    pub fn progress(&self, now: DateTime<FixedOffset>) -> f64 {
+        let duration = (self.stop - self.start).num_seconds();
+        if duration <= 0 {
+            return 1.0;
+        }
+        let elapsed = (now - self.start).num_seconds();
+
+        (elapsed as f64 / duration as f64).clamp(0.0, 1.0)
+    }
+}
+
+/// Returns the programme currently airing and the next programme of each channel that is accepted
+/// by the channel filter of [`Options::include_channels`] and [`Options::exclude_channels`].
+///
+/// The current time is [`Options::now`]. A programme is airing if it started and has not stopped yet.
+/// The channels are in channel order, followed by the channels of programmes without `<channel>` element.
+pub fn now_next(listing: &Tv, options: &Options) -> Result<Vec<NowNext>, Error> {
+    let language = options.language.filter(|l| !l.is_empty());
+    let now = options.now();
+    let mut filter = ChannelFilter::new(options);
+
+    let mut channels: Vec<_> = listing
+        .channels
+        .iter()
+        .filter(|channel| filter.accept_channel(channel))
+        .map(|channel| now_next_channel(channel, language))
+        .collect();
+
+    let programmes = if options.infer_stop_times {
+        let mut programmes = listing.programmes.clone();
+        infer_stop_times(&mut programmes, options);
+        Cow::Owned(programmes)
+    } else {
+        Cow::Borrowed(listing.programmes.as_slice())
+    };
+
+    for (index, programme) in programmes.iter().enumerate() {
+        if !filter.accept_channel_id(&programme.channel) {
+            continue;
+        }
+
+        let (start, stop) = match parse_programme_times(programme, options) {
+            Ok(times) => times,
+            Err(err) => {
+                skip_programme(ProgrammeContext::new(programme, index, None), err, options)?;
+                continue;
+            }
+        };
+        let channel_id = &programme.channel;
+        let programme = NowProgramme {
+            title: find_value(&programme.titles, language).trim().to_string(),
+            start: options.to_output_timezone(start),
+            stop: options.to_output_timezone(stop),
+        };
+
+        let channel = match channels.iter().position(|c| c.channel_id == *channel_id) {
+            Some(i) => &mut channels[i],
+            None => {
+                channels.push(NowNext {
+                    channel_id: channel_id.clone(),
+                    channel_name: channel_id.clone(),
+                    ..Default::default()
+                });
+                channels.last_mut().unwrap()
+            }
+        };
+
+        if programme.start <= now && now < programme.stop {
+            // Of overlapping programmes, the one that started last
+            if channel
+                .now
+                .as_ref()
+                .is_none_or(|p| p.start < programme.start)
+            {
+                channel.now = Some(programme);
+            }
+        } else if programme.start > now
+            && channel
+                .next
+                .as_ref()
+                .is_none_or(|p| programme.start < p.start)
+        {
+            channel.next = Some(programme);
+        }
+    }
+
+    Ok(channels)
+}
+
+fn now_next_channel(channel: &Channel, language: Option<&str>) -> NowNext {
+    NowNext {
+        channel_id: channel.id.clone(),
+        channel_name: channel_name(channel, language).to_string(),
+        ..Default::default()
+    }
+}
+
+/// Writes a table with a row per channel, with the programme currently airing and its progress,
+/// and the next programme. The rows are truncated to the width in terminal columns.
+pub fn write_table(
+    mut writer: impl Write,
+    channels: &[NowNext],
+    options: &Options,
+    width: usize,
+) -> Result<(), Error> {
+    let now = options.now();
+    let time = |datetime: DateTime<FixedOffset>| datetime.format(options.time_format).to_string();
+
+    // Column widths: the channel names, and the rest split between now and next
+    let channel_width = channels
+        .iter()
+        .map(|channel| channel.channel_name.width())
+        .chain(["Channel".len()])
+        .max()
+        .unwrap_or_default()
+        .min(width / 4);
+    let rest = width.saturating_sub(channel_width + 4);
+    let now_width = rest * 3 / 5;
+    let next_width = rest - now_width;
+
+    let mut write_row = |channel: &str, now: &str, next: &str| {
+        let row = format!(
+            "{}  {}  {}",
+            fit(channel, channel_width),
+            fit(now, now_width),
+            fit(next, next_width)
+        );
+        writeln!(writer, "{}", row.trim_end())
+    };
+
+    write_row("Channel", "Now", "Next")?;
+    for channel in channels {
+        let current = match &channel.now {
+            Some(programme) => {
+                let filled = (programme.progress(now) * PROGRESS_BAR_WIDTH as f64).round() as usize;
+                format!(
+                    "[{}{}] {}-{} {}",
+                    "#".repeat(filled),
+                    "-".repeat(PROGRESS_BAR_WIDTH - filled),
+                    time(programme.start),
+                    time(programme.stop),
+                    programme.title
+                )
+            }
+            None => "-".to_string(),
+        };
+        let next = match &channel.next {
+            Some(programme) => format!("{} {}", time(programme.start), programme.title),
+            None => "-".to_string(),
+        };
+        write_row(&channel.channel_name, &current, &next)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Returns the text truncated with "…" or padded with spaces, to the width in terminal columns.
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted = String::new();
+    if text.width() <= width {
+        fitted.push_str(text);
+    } else if width > 0 {
+        let mut fitted_width = 0;
+        for c in text.chars() {
+            let char_width = c.width().unwrap_or_default();
+            if fitted_width + char_width > width - 1 {
+                break;
+            }
+            fitted.push(c);
+            fitted_width += char_width;
+        }
+        fitted.push('…');
+    }
+
+    let padding = width.saturating_sub(fitted.width());
+    fitted.extend(std::iter::repeat_n(' ', padding));
+
+    fitted
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::xmltv::reader::Reader;
+
+    const INPUT: &str = r#"<tv>
+  <channel id="a.tv"><display-name>A TV</display-name></channel>
+  <channel id="b.tv"><display-name>B TV</display-name></channel>
+  <channel id="c.tv"><display-name>C TV</display-name></channel>
+  <programme channel="a.tv" start="20240101100000 +0000" stop="20240101110000 +0000"><title>Morning news</title></programme>
+  <programme channel="a.tv" start="20240101113000 +0000" stop="20240101120000 +0000"><title>Later</title></programme>
+  <programme channel="a.tv" start="20240101110000 +0000" stop="20240101113000 +0000"><title>Weather</title></programme>
+  <programme channel="b.tv" start="20240101090000 +0000" stop="20240101100000 +0000"><title>Ended</title></programme>
+  <programme channel="d.tv" start="20240101120000 +0000" stop="20240101130000 +0000"><title>Lunch with a very long title</title></programme>
+</tv>"#;
+
+    fn options(exclude: &[crate::filter::ChannelPattern]) -> Options<'_> {
+        Options {
+            exclude_channels: exclude,
+            now: Some(DateTime::parse_from_rfc3339("2024-01-01T10:15:00+00:00").unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_now_next() {
+        let listing = Reader::new(INPUT.as_bytes()).read_listing().unwrap();
+        let exclude = ["c.tv".parse().unwrap()];
+        let options = options(&exclude);
+
+        let channels = now_next(&listing, &options).unwrap();
+        let summary: Vec<_> = channels
+            .iter()
+            .map(|channel| {
+                (
+                    channel.channel_name.as_str(),
+                    channel.now.as_ref().map(|p| p.title.as_str()),
+                    channel.next.as_ref().map(|p| p.title.as_str()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("A TV", Some("Morning news"), Some("Weather")),
+                ("B TV", None, None),
+                ("d.tv", None, Some("Lunch with a very long title")),
+            ]
+        );
+        let progress = channels[0].now.as_ref().unwrap().progress(options.now());
+        assert_eq!(progress, 0.25);
+    }
+
+    #[test]
+    fn test_write_table() {
+        let listing = Reader::new(INPUT.as_bytes()).read_listing().unwrap();
+        let options = options(&[]);
+        let channels = now_next(&listing, &options).unwrap();
+
+        let mut output = vec![];
+        write_table(&mut output, &channels, &options, 60).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+Channel  Now                            Next
+A TV     [###-------] 10:00-11:00 Mor…  11:00 Weather
+B TV     -                              -
+C TV     -                              -
+d.tv     -                              12:00 Lunch with a …
+"
+        );
+    }
+
+    #[test]
+    fn test_fit() {
+        const TESTS: [(&str, usize, &str); 4] = [
+            ("News", 6, "News  "),
+            ("Weather", 5, "Weat…"),
+            ("日本語テレビ", 5, "日本…"),
+            ("News", 0, ""),
+        ];
+        for (text, width, expected) in TESTS {
+            assert_eq!(fit(text, width), expected, "for {text:?} {width}");
+        }
+    }
+}
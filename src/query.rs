@@ -0,0 +1,641 @@
+use chrono::{DateTime, Datelike, FixedOffset, TimeDelta, Timelike, Weekday};
+use regex::Regex;
+use std::str::FromStr;
+use xmltv::{Programme, ValueAndLang};
+
+use crate::watchlist::credits;
+
+/// Query expression over programme fields, for example:
+///
+/// ```text
+/// category == "Film" and duration > 90m and not previously_shown and channel in ["bbc1", "bbc2"]
+/// ```
+///
+/// Operators, from low to high precedence: `or`, `and`, `not`, comparisons, and parentheses.
+///
+/// Comparisons:
+/// - text fields: `==`, `!=`, `in [...]`, `contains` (case-insensitive) and `~` (regular expression).
+///   A field with multiple values, for example, `category`, matches if any value matches.
+/// - number fields: `==`, `!=`, `<`, `<=`, `>`, `>=` and `in [...]`.
+/// - `duration`: the same as number fields, with a duration, for example, `90m` or `1h30m`, or a number of minutes.
+/// - `weekday`: `==`, `!=` and `in [...]`, with a weekday, for example, `"sat"` or `"Saturday"`.
+/// - flags: the flag name alone, or `==` or `!=` with `true` or `false`.
+///
+/// Text fields: `title`, `sub_title`, `description`, `category`, `keyword`, `channel` (id), `rating`
+/// and `credits` (any credited person).
+/// Number fields: `hour` (of the start time) and `star_rating` (the first star rating value, for example, 3 of "3/5").
+/// Flags: `new`, `premiere`, `last_chance` and `previously_shown`.
+///
+/// The start time, hour and weekday are in the output timezone.
+#[derive(Clone, Debug)]
+pub struct Query {
+    expr: Expr,
+}
+
+impl Query {
+    /// Returns true if the programme with the start and stop time matches the query.
+    pub fn is_match(
+        &self,
+        programme: &Programme,
+        starttime_dt: DateTime<FixedOffset>,
+        stoptime_dt: DateTime<FixedOffset>,
+    ) -> bool {
+        self.expr.eval(&Fields {
+            programme,
+            starttime_dt,
+            stoptime_dt,
+        })
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    /// Parses a query expression. See [`Query`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {token}"));
+        }
+
+        Ok(Self { expr })
+    }
+}
+
+//
+
+/// Programme field values.
+struct Fields<'a> {
+    programme: &'a Programme,
+    starttime_dt: DateTime<FixedOffset>,
+    stoptime_dt: DateTime<FixedOffset>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TextField {
+    Title,
+    SubTitle,
+    Description,
+    Category,
+    Keyword,
+    Channel,
+    Rating,
+    Credits,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NumberField {
+    Hour,
+    StarRating,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Flag {
+    New,
+    Premiere,
+    LastChance,
+    PreviouslyShown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Text(TextField),
+    Number(NumberField),
+    Duration,
+    Weekday,
+    Flag(Flag),
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let field = match s.replace('-', "_").as_str() {
+            "title" => Field::Text(TextField::Title),
+            "sub_title" => Field::Text(TextField::SubTitle),
+            "description" => Field::Text(TextField::Description),
+            "category" => Field::Text(TextField::Category),
+            "keyword" => Field::Text(TextField::Keyword),
+            "channel" => Field::Text(TextField::Channel),
+            "rating" => Field::Text(TextField::Rating),
+            "credits" => Field::Text(TextField::Credits),
+            "hour" => Field::Number(NumberField::Hour),
+            "star_rating" => Field::Number(NumberField::StarRating),
+            "duration" => Field::Duration,
+            "weekday" => Field::Weekday,
+            "new" => Field::Flag(Flag::New),
+            "premiere" => Field::Flag(Flag::Premiere),
+            "last_chance" => Field::Flag(Flag::LastChance),
+            "previously_shown" => Field::Flag(Flag::PreviouslyShown),
+            _ => return Err(format!("unknown field {s:?}")),
+        };
+
+        Ok(field)
+    }
+}
+
+impl Fields<'_> {
+    fn texts(&self, field: TextField) -> Vec<&str> {
+        fn values(values: &[ValueAndLang]) -> Vec<&str> {
+            values.iter().map(|value| value.value.as_str()).collect()
+        }
+
+        let programme = self.programme;
+        match field {
+            TextField::Title => values(&programme.titles),
+            TextField::SubTitle => values(&programme.sub_titles),
+            TextField::Description => values(&programme.descriptions),
+            TextField::Category => programme
+                .categories
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect(),
+            TextField::Keyword => values(&programme.keywords),
+            TextField::Channel => vec![programme.channel.as_str()],
+            TextField::Rating => programme.ratings.iter().map(|r| r.value.as_str()).collect(),
+            TextField::Credits => credits(programme).collect(),
+        }
+    }
+
+    fn number(&self, field: NumberField) -> Option<f64> {
+        match field {
+            NumberField::Hour => Some(self.starttime_dt.hour().into()),
+            NumberField::StarRating => {
+                let star_rating = self.programme.star_ratings.first()?;
+                let value = star_rating.value.split('/').next()?;
+                value.trim().parse().ok()
+            }
+        }
+    }
+
+    fn flag(&self, flag: Flag) -> bool {
+        let programme = self.programme;
+        match flag {
+            Flag::New => programme.new,
+            Flag::Premiere => programme.premiere.is_some(),
+            Flag::LastChance => programme.last_chance.is_some(),
+            Flag::PreviouslyShown => programme.previously_shown.is_some(),
+        }
+    }
+}
+
+//
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    fn eval<T: PartialOrd>(self, lhs: T, rhs: T) -> bool {
+        match self {
+            CmpOp::Eq => lhs == rhs,
+            CmpOp::Ne => lhs != rhs,
+            CmpOp::Lt => lhs < rhs,
+            CmpOp::Le => lhs <= rhs,
+            CmpOp::Gt => lhs > rhs,
+            CmpOp::Ge => lhs >= rhs,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Flag(Flag),
+    /// Any value equals the lowercase text
+    TextEq(TextField, String),
+    /// Any value contains the lowercase text
+    TextContains(TextField, String),
+    /// Any value matches the regular expression
+    TextMatches(TextField, Regex),
+    Number(NumberField, CmpOp, f64),
+    Duration(CmpOp, TimeDelta),
+    Weekday(Weekday),
+}
+
+impl Expr {
+    fn eval(&self, fields: &Fields) -> bool {
+        match self {
+            Expr::Or(lhs, rhs) => lhs.eval(fields) || rhs.eval(fields),
+            Expr::And(lhs, rhs) => lhs.eval(fields) && rhs.eval(fields),
+            Expr::Not(expr) => !expr.eval(fields),
+            Expr::Flag(flag) => fields.flag(*flag),
+            Expr::TextEq(field, text) => fields
+                .texts(*field)
+                .iter()
+                .any(|value| value.trim().to_lowercase() == *text),
+            Expr::TextContains(field, text) => fields
+                .texts(*field)
+                .iter()
+                .any(|value| value.to_lowercase().contains(text)),
+            Expr::TextMatches(field, regex) => fields
+                .texts(*field)
+                .iter()
+                .any(|value| regex.is_match(value)),
+            Expr::Number(field, op, number) => fields
+                .number(*field)
+                .is_some_and(|value| op.eval(value, *number)),
+            Expr::Duration(op, duration) => {
+                op.eval(fields.stoptime_dt - fields.starttime_dt, *duration)
+            }
+            Expr::Weekday(weekday) => fields.starttime_dt.weekday() == *weekday,
+        }
+    }
+}
+
+//
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Text(String),
+    Number(f64),
+    Duration(TimeDelta),
+    Cmp(CmpOp),
+    Tilde,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "{ident:?}"),
+            Token::Text(text) => write!(f, "text {text:?}"),
+            Token::Number(number) => write!(f, "number {number}"),
+            Token::Duration(duration) => write!(f, "duration {duration}"),
+            Token::Cmp(op) => write!(f, "operator {op:?}"),
+            Token::Tilde => write!(f, "\"~\""),
+            Token::LParen => write!(f, "\"(\""),
+            Token::RParen => write!(f, "\")\""),
+            Token::LBracket => write!(f, "\"[\""),
+            Token::RBracket => write!(f, "\"]\""),
+            Token::Comma => write!(f, "\",\""),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            ',' => Token::Comma,
+            '~' => Token::Tilde,
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let eq = chars.next_if_eq(&'=').is_some();
+                let op = match (c, eq) {
+                    ('=', true) => CmpOp::Eq,
+                    ('!', true) => CmpOp::Ne,
+                    ('<', false) => CmpOp::Lt,
+                    ('<', true) => CmpOp::Le,
+                    ('>', false) => CmpOp::Gt,
+                    ('>', true) => CmpOp::Ge,
+                    _ => return Err(format!("unexpected character {c:?}")),
+                };
+                tokens.push(Token::Cmp(op));
+                continue;
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => text.extend(chars.next()),
+                        Some(c) => text.push(c),
+                        None => return Err("unterminated text".to_string()),
+                    }
+                }
+                tokens.push(Token::Text(text));
+                continue;
+            }
+            _ if c.is_ascii_digit() => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '.') {
+                    word.push(c);
+                }
+                let token = match word.parse() {
+                    Ok(number) if f64::is_finite(number) => Token::Number(number),
+                    _ => humantime::parse_duration(&word)
+                        .ok()
+                        .and_then(|duration| TimeDelta::from_std(duration).ok())
+                        .map(Token::Duration)
+                        .ok_or_else(|| format!("invalid number or duration {word:?}"))?,
+                };
+                tokens.push(token);
+                continue;
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
+                {
+                    word.push(c);
+                }
+                tokens.push(Token::Ident(word));
+                continue;
+            }
+            _ => return Err(format!("unexpected character {c:?}")),
+        };
+        chars.next();
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Literal value in a comparison.
+enum Literal {
+    Text(String),
+    Number(f64),
+    Duration(TimeDelta),
+    Bool(bool),
+}
+
+/// Recursive descent parser.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| "unexpected end of expression".to_string())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    /// Consumes the next token if it is the keyword.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found =
+            matches!(self.peek(), Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case(keyword));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected {expected}, found {token}")),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let field = match self.next()? {
+            Token::LParen => {
+                let expr = self.parse_or()?;
+                self.expect(Token::RParen)?;
+                return Ok(expr);
+            }
+            Token::Ident(ident) => ident.parse::<Field>()?,
+            token => return Err(format!("expected field, found {token}")),
+        };
+
+        if self.keyword("in") {
+            self.expect(Token::LBracket)?;
+            let mut expr: Option<Expr> = None;
+            loop {
+                if self.peek() == Some(&Token::RBracket) && expr.is_some() {
+                    break;
+                }
+                let literal = self.parse_literal()?;
+                let eq = comparison(field, CmpOp::Eq, literal)?;
+                expr = Some(match expr {
+                    Some(expr) => Expr::Or(Box::new(expr), Box::new(eq)),
+                    None => eq,
+                });
+                if self.peek() != Some(&Token::Comma) {
+                    break;
+                }
+                self.pos += 1;
+            }
+            self.expect(Token::RBracket)?;
+            return Ok(expr.expect("non-empty list"));
+        }
+
+        if self.keyword("contains") {
+            let Field::Text(field) = field else {
+                return Err("\"contains\" requires a text field".to_string());
+            };
+            let Literal::Text(text) = self.parse_literal()? else {
+                return Err("\"contains\" requires a text".to_string());
+            };
+            return Ok(Expr::TextContains(field, text.to_lowercase()));
+        }
+
+        match self.peek() {
+            Some(Token::Tilde) => {
+                self.pos += 1;
+                let Field::Text(field) = field else {
+                    return Err("\"~\" requires a text field".to_string());
+                };
+                let Literal::Text(pattern) = self.parse_literal()? else {
+                    return Err("\"~\" requires a regular expression text".to_string());
+                };
+                let regex = Regex::new(&pattern).map_err(|err| format!("{err}"))?;
+                Ok(Expr::TextMatches(field, regex))
+            }
+            Some(&Token::Cmp(op)) => {
+                self.pos += 1;
+                let literal = self.parse_literal()?;
+                comparison(field, op, literal)
+            }
+            _ => match field {
+                Field::Flag(flag) => Ok(Expr::Flag(flag)),
+                _ => Err("expected comparison".to_string()),
+            },
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal, String> {
+        match self.next()? {
+            Token::Text(text) => Ok(Literal::Text(text)),
+            Token::Number(number) => Ok(Literal::Number(number)),
+            Token::Duration(duration) => Ok(Literal::Duration(duration)),
+            Token::Ident(ident) if ident == "true" => Ok(Literal::Bool(true)),
+            Token::Ident(ident) if ident == "false" => Ok(Literal::Bool(false)),
+            token => Err(format!("expected value, found {token}")),
+        }
+    }
+}
+
+/// Returns the comparison of the field with the literal value, if their types match.
+fn comparison(field: Field, op: CmpOp, literal: Literal) -> Result<Expr, String> {
+    let negate = |expr, op| match op {
+        CmpOp::Eq => Ok(expr),
+        CmpOp::Ne => Ok(Expr::Not(Box::new(expr))),
+        _ => Err(format!("{field:?} can only be compared with == or !=")),
+    };
+
+    match (field, literal) {
+        (Field::Text(field), Literal::Text(text)) => {
+            negate(Expr::TextEq(field, text.trim().to_lowercase()), op)
+        }
+        (Field::Number(field), Literal::Number(number)) => Ok(Expr::Number(field, op, number)),
+        (Field::Duration, Literal::Duration(duration)) => Ok(Expr::Duration(op, duration)),
+        (Field::Duration, Literal::Number(minutes)) => {
+            let duration = TimeDelta::try_seconds((minutes * 60.0).round() as i64)
+                .ok_or_else(|| format!("duration of {minutes} minutes out of range"))?;
+            Ok(Expr::Duration(op, duration))
+        }
+        (Field::Weekday, Literal::Text(text)) => {
+            let weekday = text
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid weekday {text:?}"))?;
+            negate(Expr::Weekday(weekday), op)
+        }
+        (Field::Flag(flag), Literal::Bool(value)) => negate(
+            if value {
+                Expr::Flag(flag)
+            } else {
+                Expr::Not(Box::new(Expr::Flag(flag)))
+            },
+            op,
+        ),
+        (field, _) => Err(format!("invalid value type for {field:?}")),
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use xmltv::{NameAndLang, StarRating};
+
+    use super::*;
+
+    #[test]
+    fn test() {
+        let programme = Programme {
+            channel: "bbc1".to_string(),
+            titles: vec![ValueAndLang {
+                value: "The Film".to_string(),
+                lang: None,
+            }],
+            categories: vec![
+                NameAndLang {
+                    name: "Film".to_string(),
+                    lang: None,
+                },
+                NameAndLang {
+                    name: "Drama".to_string(),
+                    lang: None,
+                },
+            ],
+            star_ratings: vec![StarRating {
+                value: "4/5".to_string(),
+                ..Default::default()
+            }],
+            new: true,
+            ..Default::default()
+        };
+        let datetime = |datetime| DateTime::parse_from_rfc3339(datetime).unwrap();
+        // Saturday
+        let starttime_dt = datetime("2024-01-06T20:00:00+01:00");
+        let stoptime_dt = datetime("2024-01-06T21:45:00+01:00");
+
+        const TESTS: [(&str, bool); 19] = [
+            (r#"category == "Film""#, true),
+            (r#"category == "film" and category == "drama""#, true),
+            (r#"category != "Film""#, false),
+            (r#"category == "News" or title contains "film""#, true),
+            ("duration > 90m", true),
+            ("duration > 1h45m", false),
+            ("duration >= 105", true),
+            ("not previously_shown and new", true),
+            ("premiere == false", true),
+            (r#"channel in ["bbc1", "bbc2"]"#, true),
+            (r#"channel in ["bbc2"]"#, false),
+            ("hour >= 20 and hour < 23", true),
+            ("star_rating >= 4", true),
+            (r#"weekday in ["sat", "Sunday"]"#, true),
+            (r#"weekday == "mon""#, false),
+            (r#"title ~ "^The ""#, true),
+            (r#"not (new or premiere)"#, false),
+            (
+                r#"category == "Film" and duration > 90m and not previously_shown and channel in ["bbc1","bbc2"]"#,
+                true,
+            ),
+            (r#"last-chance or sub_title == "x""#, false),
+        ];
+        for (query, expected) in TESTS {
+            let query: Query = query.parse().unwrap();
+            assert_eq!(
+                query.is_match(&programme, starttime_dt, stoptime_dt),
+                expected,
+                "for {query:?}"
+            );
+        }
+
+        for query in [
+            "",
+            "unknown == 1",
+            "category > \"Film\"",
+            "duration == \"long\"",
+            "hour in []",
+            "new and",
+            "(new",
+            "title ~ \"(\"",
+            "duration > 1e20",
+            "duration > 1e400",
+        ] {
+            assert!(query.parse::<Query>().is_err(), "for {query:?}");
+        }
+    }
+}
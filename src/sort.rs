@@ -0,0 +1,103 @@
+use clap::ValueEnum;
+use std::cmp::Reverse;
+
+use crate::export::{Options, PendingProgramme};
+use crate::xmltv::find_value;
+
+/// Sort order of the feed items.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum SortOrder {
+    /// By start time
+    Start,
+
+    /// By channel id, then by start time
+    Channel,
+
+    /// By title, case-insensitive, then by start time
+    Title,
+
+    /// By start time, latest first
+    Reverse,
+}
+
+/// Sorts the programmes. Programmes that sort equal keep their order.
+pub(crate) fn sort_programmes(
+    programmes: &mut [PendingProgramme],
+    order: SortOrder,
+    options: &Options,
+) {
+    match order {
+        SortOrder::Start => programmes.sort_by_key(|pending| pending.airing.start),
+        SortOrder::Channel => programmes.sort_by(|a, b| {
+            (&a.airing.channel, a.airing.start).cmp(&(&b.airing.channel, b.airing.start))
+        }),
+        SortOrder::Title => {
+            let language = options.language.filter(|l| !l.is_empty());
+            programmes.sort_by_cached_key(|pending| {
+                let title = find_value(&pending.programme.titles, language).to_lowercase();
+                (title, pending.airing.start)
+            })
+        }
+        SortOrder::Reverse => programmes.sort_by_key(|pending| Reverse(pending.airing.start)),
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use pretty_assertions::assert_eq;
+    use xmltv::{Programme, ValueAndLang};
+
+    use super::*;
+    use crate::error::ProgrammeContext;
+    use crate::export::{Airing, Annotations};
+
+    fn pending(index: usize, channel: &str, start: &str, title: &str) -> PendingProgramme<'static> {
+        let programme = Programme {
+            channel: channel.to_string(),
+            start: start.to_string(),
+            titles: vec![ValueAndLang {
+                value: title.to_string(),
+                lang: None,
+            }],
+            ..Default::default()
+        };
+        let start = DateTime::parse_from_rfc3339(start).unwrap();
+
+        PendingProgramme {
+            context: ProgrammeContext::new(&programme, index, None),
+            programme,
+            airing: Airing {
+                channel: channel.to_string(),
+                start,
+                stop: start,
+            },
+            annotations: Annotations::default(),
+        }
+    }
+
+    #[test]
+    fn test() {
+        // Indices of the sorted programmes
+        const TESTS: [(SortOrder, [usize; 4]); 4] = [
+            (SortOrder::Start, [1, 0, 3, 2]),
+            (SortOrder::Channel, [0, 2, 1, 3]),
+            (SortOrder::Title, [3, 1, 2, 0]),
+            (SortOrder::Reverse, [2, 0, 3, 1]),
+        ];
+        for (order, expected) in TESTS {
+            let mut programmes = [
+                pending(0, "a", "2024-01-01T11:00:00+01:00", "Tea"),
+                pending(1, "b", "2024-01-01T09:00:00+00:00", "news"),
+                pending(2, "a", "2024-01-01T12:00:00+00:00", "News"),
+                pending(3, "c", "2024-01-01T10:00:00+00:00", "Apple"),
+            ];
+            sort_programmes(&mut programmes, order, &Options::default());
+
+            let indices = programmes.each_ref().map(|pending| pending.context.index);
+            assert_eq!(indices, expected, "for {order:?}");
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use clap::ValueEnum;
+use quick_xml::escape::escape;
+use std::collections::HashSet;
+use std::io::Write;
+use xmltv::{Channel, Tv};
+
+use crate::error::Error;
+use crate::export::{channel_name, Options};
+use crate::filter::ChannelFilter;
+
+/// Split mode of the output feed.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum SplitBy {
+    /// One feed per XMLTV channel
+    Channel,
+}
+
+/// XMLTV TV listing of one channel. See [`split_by_channel`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChannelListing {
+    /// File name without extension, derived from the sanitised channel id.
+    /// Unique among the split listings.
+    pub file_stem: String,
+
+    /// The listing with only the channel and its programmes
+    pub listing: Tv,
+}
+
+impl ChannelListing {
+    pub fn channel(&self) -> &Channel {
+        &self.listing.channels[0]
+    }
+
+    /// Returns the channel display name, or else the channel id.
+    pub fn title<'a>(&'a self, language: Option<&'a str>) -> &'a str {
+        channel_name(self.channel(), language)
+    }
+
+    /// Returns the URL of the first channel icon, if any.
+    pub fn icon(&self) -> Option<&str> {
+        self.channel()
+            .icons
+            .iter()
+            .map(|icon| icon.trim())
+            .find(|icon| !icon.is_empty())
+    }
+}
+
+/// Splits the XMLTV TV listing into one listing per channel that is accepted by the channel filter
+/// of [`Options::include_channels`] and [`Options::exclude_channels`].
+///
+/// The listings are in channel order, followed by the channels of programmes without `<channel>` element.
+pub fn split_by_channel(listing: &Tv, options: &Options) -> Vec<ChannelListing> {
+    let mut filter = ChannelFilter::new(options);
+
+    let mut channels: Vec<_> = listing
+        .channels
+        .iter()
+        .filter(|channel| filter.accept_channel(channel))
+        .cloned()
+        .collect();
+    for programme in &listing.programmes {
+        if !channels
+            .iter()
+            .any(|channel| channel.id == programme.channel)
+            && filter.accept_channel_id(&programme.channel)
+        {
+            channels.push(Channel {
+                id: programme.channel.clone(),
+                ..Default::default()
+            });
+        }
+    }
+
+    let mut file_stems = FileStems::default();
+    channels
+        .into_iter()
+        .map(|channel| {
+            let programmes = listing
+                .programmes
+                .iter()
+                .filter(|programme| programme.channel == channel.id)
+                .cloned()
+                .collect();
+
+            ChannelListing {
+                file_stem: file_stems.next(&channel.id),
+                listing: Tv {
+                    channels: vec![channel],
+                    programmes,
+                    ..tv_attributes(listing)
+                },
+            }
+        })
+        .collect()
+}
+
+/// Returns a file name without extension for the channel id. Characters other than ASCII letters,
+/// digits, '-', '_' and '.' are replaced with '_'. Leading dots are removed.
+pub fn sanitise_file_stem(channel_id: &str) -> String {
+    let file_stem: String = channel_id
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let file_stem = file_stem.trim_start_matches('.');
+
+    if !file_stem.is_empty() {
+        file_stem.to_string()
+    } else {
+        "_".to_string()
+    }
+}
+
+/// Unique file names without extension, for channel ids. See [`sanitise_file_stem`].
+///
+/// A file name that is already taken, case-insensitively, gets a "-2", "-3", etc. suffix.
+#[derive(Default)]
+pub(crate) struct FileStems {
+    taken: HashSet<String>,
+}
+
+impl FileStems {
+    pub(crate) fn next(&mut self, channel_id: &str) -> String {
+        let sanitised = sanitise_file_stem(channel_id);
+        let mut file_stem = sanitised.clone();
+        let mut n = 1;
+        while !self.taken.insert(file_stem.to_lowercase()) {
+            n += 1;
+            file_stem = format!("{sanitised}-{n}");
+        }
+
+        file_stem
+    }
+}
+
+/// Writes an HTML index page that links to the feed files. A feed is a title and a file name.
+pub fn write_index(
+    mut writer: impl Write,
+    title: &str,
+    feeds: &[(String, String)],
+) -> Result<(), Error> {
+    let title = escape(title);
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html>")?;
+    writeln!(writer, "<head>")?;
+    writeln!(writer, "<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>{title}</title>")?;
+    writeln!(writer, "</head>")?;
+    writeln!(writer, "<body>")?;
+    writeln!(writer, "<h1>{title}</h1>")?;
+    writeln!(writer, "<ul>")?;
+    for (feed_title, file_name) in feeds {
+        writeln!(
+            writer,
+            "<li><a href=\"{}\">{}</a></li>",
+            escape(file_name),
+            escape(feed_title)
+        )?;
+    }
+    writeln!(writer, "</ul>")?;
+    writeln!(writer, "</body>")?;
+    writeln!(writer, "</html>")?;
+
+    Ok(())
+}
+
+/// Returns the attributes of the `<tv>` element, without channels and programmes.
+fn tv_attributes(listing: &Tv) -> Tv {
+    Tv {
+        source_info_url: listing.source_info_url.clone(),
+        source_info_name: listing.source_info_name.clone(),
+        source_data_url: listing.source_data_url.clone(),
+        generator_info_name: listing.generator_info_name.clone(),
+        generator_info_url: listing.generator_info_url.clone(),
+        channels: vec![],
+        programmes: vec![],
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use xmltv::{NameAndLang, Programme};
+
+    use super::*;
+
+    #[test]
+    fn test_sanitise_file_stem() {
+        const TESTS: [(&str, &str); 5] = [
+            ("bbc1.bbc.co.uk", "bbc1.bbc.co.uk"),
+            ("NPO 1", "NPO_1"),
+            ("../etc/passwd", "_etc_passwd"),
+            ("a/b\\c:d", "a_b_c_d"),
+            ("", "_"),
+        ];
+        for (channel_id, expected) in TESTS {
+            assert_eq!(
+                sanitise_file_stem(channel_id),
+                expected,
+                "for {channel_id:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_by_channel() {
+        let programme = |channel: &str| Programme {
+            channel: channel.to_string(),
+            ..Default::default()
+        };
+        let listing = Tv {
+            channels: vec![
+                Channel {
+                    id: "a/1".to_string(),
+                    display_names: vec![NameAndLang {
+                        name: "A One".to_string(),
+                        lang: None,
+                    }],
+                    icons: vec!["https://example.com/a.png".to_string()],
+                    ..Default::default()
+                },
+                Channel {
+                    id: "a:1".to_string(),
+                    ..Default::default()
+                },
+                Channel {
+                    id: "b".to_string(),
+                    ..Default::default()
+                },
+            ],
+            programmes: vec![programme("a/1"), programme("c"), programme("a/1")],
+            ..Default::default()
+        };
+        let exclude = ["b".parse().unwrap()];
+        let options = Options {
+            exclude_channels: &exclude,
+            ..Default::default()
+        };
+
+        let listings = split_by_channel(&listing, &options);
+        let summary: Vec<_> = listings
+            .iter()
+            .map(|listing| {
+                (
+                    listing.file_stem.as_str(),
+                    listing.title(None),
+                    listing.icon(),
+                    listing.listing.programmes.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("a_1", "A One", Some("https://example.com/a.png"), 2),
+                ("a_1-2", "a:1", None, 0),
+                ("c", "c", None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_index() {
+        let feeds = [("A & B".to_string(), "a_b.xml".to_string())];
+        let mut index = vec![];
+        write_index(&mut index, "Feeds", &feeds).unwrap();
+
+        let index = String::from_utf8(index).unwrap();
+        assert!(index.contains("<title>Feeds</title>"));
+        assert!(index.contains("<li><a href=\"a_b.xml\">A &amp; B</a></li>"));
+    }
+}
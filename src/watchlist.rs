@@ -0,0 +1,373 @@
+use chrono::{DateTime, FixedOffset, NaiveTime};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use xmltv::{Channel, Programme, ValueAndLang};
+
+use crate::error::Error;
+
+/// Watchlist of named rules, read from a TOML file. For example:
+///
+/// ```toml
+/// [[rule]]
+/// name = "Doctor Who"
+/// title = "regex:^Doctor Who$"
+///
+/// [[rule]]
+/// name = "Evening films"
+/// category = "film"
+/// time = "18:00-23:00"
+/// ```
+///
+/// A programme matches the watchlist if it matches any rule.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Watchlist {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+/// Watchlist rule. A programme matches the rule if it matches all the specified fields.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Rule {
+    pub name: String,
+
+    /// Matches any title
+    pub title: Option<TextPattern>,
+
+    /// Matches any sub-title
+    pub sub_title: Option<TextPattern>,
+
+    /// Matches any description
+    pub description: Option<TextPattern>,
+
+    /// Matches any category
+    pub category: Option<TextPattern>,
+
+    /// Matches the channel id or any channel display name
+    pub channel: Option<TextPattern>,
+
+    /// Matches any credited person: director, actor, writer, presenter, etc.
+    pub credits: Option<TextPattern>,
+
+    /// Matches the start time of day, in the output timezone
+    pub time: Option<TimeOfDay>,
+}
+
+/// Text pattern. A case-insensitive substring, or with prefix "regex:" a regular expression.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub enum TextPattern {
+    /// Lowercase substring
+    Substring(String),
+    Regex(Regex),
+}
+
+impl TextPattern {
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            TextPattern::Substring(substring) => text.to_lowercase().contains(substring),
+            TextPattern::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+impl FromStr for TextPattern {
+    type Err = String;
+
+    /// Parses a text pattern. See [`TextPattern`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("regex:") {
+            Some(pattern) => Regex::new(pattern)
+                .map(TextPattern::Regex)
+                .map_err(|err| format!("{err}")),
+            None => Ok(TextPattern::Substring(s.to_lowercase())),
+        }
+    }
+}
+
+impl TryFrom<String> for TextPattern {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Time of day range "hh:mm-hh:mm". The range may wrap around midnight, for example, "22:00-02:00".
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay {
+    pub from: NaiveTime,
+
+    /// Exclusive
+    pub until: NaiveTime,
+}
+
+impl TimeOfDay {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.until {
+            self.from <= time && time < self.until
+        } else {
+            self.from <= time || time < self.until
+        }
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    /// Parses a time of day range. See [`TimeOfDay`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|err| format!("invalid time of day {s:?}: {err}"))
+        };
+
+        let (from, until) = s
+            .split_once('-')
+            .ok_or_else(|| format!("invalid time of day {s:?}: expected \"hh:mm-hh:mm\""))?;
+
+        Ok(Self {
+            from: parse(from)?,
+            until: parse(until)?,
+        })
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Watchlist {
+    /// Reads a watchlist from a TOML file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        fs::read_to_string(path)?.parse()
+    }
+}
+
+impl FromStr for Watchlist {
+    type Err = Error;
+
+    /// Parses a watchlist from TOML.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+impl Rule {
+    /// Returns true if the programme, on the channel and starting at the time, matches the rule.
+    pub fn is_match(
+        &self,
+        programme: &Programme,
+        channel_names: &[String],
+        starttime_dt: DateTime<FixedOffset>,
+    ) -> bool {
+        fn any<'a>(
+            pattern: &Option<TextPattern>,
+            mut texts: impl Iterator<Item = &'a str>,
+        ) -> bool {
+            pattern
+                .as_ref()
+                .is_none_or(|pattern| texts.any(|text| pattern.is_match(text)))
+        }
+
+        fn values(values: &[ValueAndLang]) -> impl Iterator<Item = &str> {
+            values.iter().map(|value| value.value.as_str())
+        }
+
+        any(&self.title, values(&programme.titles))
+            && any(&self.sub_title, values(&programme.sub_titles))
+            && any(&self.description, values(&programme.descriptions))
+            && any(
+                &self.category,
+                programme.categories.iter().map(|c| c.name.as_str()),
+            )
+            && any(
+                &self.channel,
+                [programme.channel.as_str()]
+                    .into_iter()
+                    .chain(channel_names.iter().map(String::as_str)),
+            )
+            && any(&self.credits, credits(programme))
+            && self
+                .time
+                .is_none_or(|time| time.contains(starttime_dt.time()))
+    }
+}
+
+/// Returns the names of the people credited for the programme.
+pub(crate) fn credits(programme: &Programme) -> impl Iterator<Item = &str> {
+    programme.credits.iter().flat_map(|credits| {
+        let people = [
+            &credits.directors,
+            &credits.writers,
+            &credits.adapters,
+            &credits.producers,
+            &credits.composers,
+            &credits.editors,
+            &credits.presenters,
+            &credits.commentators,
+            &credits.guests,
+        ];
+        people
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .chain(credits.actors.iter().map(|actor| actor.name.as_str()))
+    })
+}
+
+/// Matches programmes against the watchlist of [`Options::watchlist`](crate::export::Options::watchlist).
+pub(crate) struct WatchlistMatcher<'a> {
+    watchlist: &'a Watchlist,
+
+    /// Channel display names, by channel id
+    channel_names: HashMap<String, Vec<String>>,
+}
+
+impl<'a> WatchlistMatcher<'a> {
+    pub(crate) fn new(watchlist: &'a Watchlist) -> Self {
+        Self {
+            watchlist,
+            channel_names: HashMap::new(),
+        }
+    }
+
+    /// Remembers the display names of the channel.
+    pub(crate) fn visit_channel(&mut self, channel: &Channel) {
+        let names = channel
+            .display_names
+            .iter()
+            .map(|name| name.name.trim().to_string())
+            .collect();
+        self.channel_names.insert(channel.id.clone(), names);
+    }
+
+    /// Returns the names of the rules that the programme matches.
+    pub(crate) fn matches(
+        &self,
+        programme: &Programme,
+        starttime_dt: DateTime<FixedOffset>,
+    ) -> Vec<&'a str> {
+        let channel_names = self
+            .channel_names
+            .get(&programme.channel)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        self.watchlist
+            .rules
+            .iter()
+            .filter(|rule| rule.is_match(programme, channel_names, starttime_dt))
+            .map(|rule| rule.name.as_str())
+            .collect()
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use xmltv::{Credits, NameAndLang};
+
+    use super::*;
+
+    const WATCHLIST: &str = r#"
+[[rule]]
+name = "Doctor Who"
+title = "regex:^Doctor Who$"
+
+[[rule]]
+name = "Evening films"
+category = "film"
+time = "18:00-23:00"
+
+[[rule]]
+name = "Night owl"
+channel = "bbc one"
+time = "23:00-02:00"
+
+[[rule]]
+name = "Tennant"
+credits = "tennant"
+"#;
+
+    fn programme(title: &str, category: &str, actor: &str) -> Programme {
+        Programme {
+            channel: "bbc1.bbc.co.uk".to_string(),
+            titles: vec![ValueAndLang {
+                value: title.to_string(),
+                lang: None,
+            }],
+            categories: vec![NameAndLang {
+                name: category.to_string(),
+                lang: None,
+            }],
+            credits: Some(Credits {
+                actors: vec![xmltv::Actor {
+                    name: actor.to_string(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test() {
+        let watchlist: Watchlist = WATCHLIST.parse().unwrap();
+        let mut matcher = WatchlistMatcher::new(&watchlist);
+        matcher.visit_channel(&Channel {
+            id: "bbc1.bbc.co.uk".to_string(),
+            display_names: vec![NameAndLang {
+                name: "BBC One".to_string(),
+                lang: None,
+            }],
+            ..Default::default()
+        });
+
+        let datetime = |datetime| DateTime::parse_from_rfc3339(datetime).unwrap();
+        let evening = datetime("2024-01-01T20:00:00+00:00");
+        let night = datetime("2024-01-01T00:30:00+00:00");
+
+        let tests = [
+            (
+                programme("Doctor Who", "Drama", "David Tennant"),
+                evening,
+                vec!["Doctor Who", "Tennant"],
+            ),
+            (
+                programme("Doctor Who Confidential", "Film", "David Tennant"),
+                evening,
+                vec!["Evening films", "Tennant"],
+            ),
+            (
+                programme("News", "News", "Someone"),
+                night,
+                vec!["Night owl"],
+            ),
+            (programme("News", "News", "Someone"), evening, vec![]),
+        ];
+        for (programme, starttime_dt, expected) in tests {
+            assert_eq!(matcher.matches(&programme, starttime_dt), expected);
+        }
+
+        assert!("[[rule]]\nname = \"x\"\nunknown = \"y\""
+            .parse::<Watchlist>()
+            .is_err());
+        assert!("[[rule]]\nname = \"x\"\ntime = \"18:00\""
+            .parse::<Watchlist>()
+            .is_err());
+    }
+}
@@ -1,11 +1,17 @@
-use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
-use xmltv::{NameAndLang, Url, ValueAndLang};
+use chrono::{
+    DateTime, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeDelta, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use xmltv::{Length, NameAndLang, Programme, Units, Url, ValueAndLang};
 
 use crate::error::Error;
+use crate::export::Options;
+
+pub mod reader;
 
 /// Datetime with timezone.
 pub const DEFAULT_XMLTV_DATETIME_FORMAT: &str = "%Y%m%d%H%M%S %z";
-/// Datetime without timezone.
+/// Datetime without timezone. Superseded by the XMLTV datetime grammar fallback.
 pub const DEFAULT_XMLTV_DATETIME_FORMAT_UTC: &str = "%Y%m%d%H%M%S";
 
 //
@@ -45,20 +51,361 @@ pub(crate) fn first_url(urls: &[Url]) -> Option<String> {
     urls.first().map(|url| &url.value).cloned()
 }
 
-/// Tries parsing with a datetime format string, which is timezone-aware.
-/// Or else tries parsing with a naive datetime format string, which has no timezone.
+/// Tries parsing with a datetime format string.
+/// Or else tries parsing with the XMLTV datetime grammar, see [`parse_xmltv_datetime`].
 pub(crate) fn parse_from_str(
     datetime: &str,
     datetime_format: &str,
-    naive_datetime_format: &str,
+    timezone: Option<Tz>,
 ) -> Result<DateTime<FixedOffset>, Error> {
     let datetime = DateTime::parse_from_str(datetime, datetime_format)
-        .or_else(|_| {
-            NaiveDateTime::parse_from_str(datetime, naive_datetime_format)
-                .map(|datetime| Utc.from_utc_datetime(&datetime).fixed_offset())
-        })?
+        .or_else(|_| parse_xmltv_datetime(datetime, timezone))?
         .to_utc()
         .into();
 
     Ok(datetime)
 }
+
+/// Parses an XMLTV datetime.
+///
+/// From the XMLTV DTD: "All dates and times in this DTD follow the same format, loosely based on ISO 8601.
+/// They can be 'YYYYMMDDhhmmss' or some initial substring, for example if you only know the year and month
+/// you can have 'YYYYMM'. You can also append a timezone to the end; if no explicit timezone is given,
+/// UTC is assumed. Examples: '200007281733 BST', '200209', '19880523083000 +0300'. (BST == +0100.)"
+///
+/// The timezone is a UTC offset ("+0100", "+01:00" or "+01"), a timezone abbreviation ("BST")
+/// or an IANA timezone name ("Europe/London").
+/// Missing date and time parts default to the start of the month, day, hour, etc.
+/// A datetime without timezone is in the specified timezone or else in UTC.
+pub(crate) fn parse_xmltv_datetime(
+    datetime: &str,
+    timezone: Option<Tz>,
+) -> Result<DateTime<FixedOffset>, Error> {
+    let invalid = |reason| Error::InvalidDatetime(datetime.to_string(), reason);
+
+    let trimmed = datetime.trim();
+    let (digits, zone) = trimmed.split_at(
+        trimmed
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(trimmed.len()),
+    );
+    if !matches!(digits.len(), 4 | 6 | 8 | 10 | 12 | 14) {
+        return Err(invalid(
+            "expected YYYY, YYYYMM, YYYYMMDD, YYYYMMDDhh, YYYYMMDDhhmm or YYYYMMDDhhmmss",
+        ));
+    }
+
+    // The digits are ASCII, so the parts always parse
+    let part = |range: std::ops::Range<usize>, default| {
+        digits
+            .get(range)
+            .map_or(default, |part| part.parse().unwrap())
+    };
+    let datetime = NaiveDate::from_ymd_opt(part(0..4, 0) as i32, part(4..6, 1), part(6..8, 1))
+        .ok_or_else(|| invalid("date out of range"))?
+        .and_hms_opt(part(8..10, 0), part(10..12, 0), part(12..14, 0))
+        .ok_or_else(|| invalid("time out of range"))?;
+
+    let zone = zone.trim();
+    if zone.is_empty() {
+        return Ok(from_local_datetime(&datetime, timezone));
+    }
+    if let Some(offset) = parse_utc_offset(zone).or_else(|| parse_timezone_abbreviation(zone)) {
+        return Ok(DateTime::from_naive_utc_and_offset(
+            datetime - offset,
+            offset,
+        ));
+    }
+    if let Ok(timezone) = zone.parse::<Tz>() {
+        return Ok(from_local_datetime(&datetime, Some(timezone)));
+    }
+
+    Err(invalid("unknown timezone"))
+}
+
+/// Parses a UTC offset: "+HHMM", "+HH:MM" or "+HH", or the same starting with '-'.
+fn parse_utc_offset(offset: &str) -> Option<FixedOffset> {
+    let sign = match offset.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+
+    let digits = offset[1..].replacen(':', "", 1);
+    if !matches!(digits.len(), 2 | 4) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[0..2].parse().ok()?;
+    let minutes: i32 = digits.get(2..4).map_or(Some(0), |m| m.parse().ok())?;
+    if minutes >= 60 {
+        return None;
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Parses a common timezone abbreviation.
+fn parse_timezone_abbreviation(abbreviation: &str) -> Option<FixedOffset> {
+    const HOUR: i32 = 3600;
+
+    let offset = match abbreviation.to_ascii_uppercase().as_str() {
+        "Z" | "UT" | "UTC" | "GMT" | "WET" => 0,
+        "BST" | "IST" | "WEST" | "CET" | "MET" => HOUR,
+        "CEST" | "MEST" | "EET" | "SAST" => 2 * HOUR,
+        "EEST" | "MSK" => 3 * HOUR,
+        "AWST" => 8 * HOUR,
+        "JST" | "KST" => 9 * HOUR,
+        "ACST" => 9 * HOUR + HOUR / 2,
+        "AEST" => 10 * HOUR,
+        "ACDT" => 10 * HOUR + HOUR / 2,
+        "AEDT" => 11 * HOUR,
+        "NZST" => 12 * HOUR,
+        "NZDT" => 13 * HOUR,
+        "NST" => -3 * HOUR - HOUR / 2,
+        "NDT" => -2 * HOUR - HOUR / 2,
+        "AST" => -4 * HOUR,
+        "ADT" => -3 * HOUR,
+        "EST" => -5 * HOUR,
+        "EDT" => -4 * HOUR,
+        "CST" => -6 * HOUR,
+        "CDT" => -5 * HOUR,
+        "MST" => -7 * HOUR,
+        "MDT" => -6 * HOUR,
+        "PST" => -8 * HOUR,
+        "PDT" => -7 * HOUR,
+        "AKST" => -9 * HOUR,
+        "AKDT" => -8 * HOUR,
+        "HST" => -10 * HOUR,
+        _ => return None,
+    };
+
+    FixedOffset::east_opt(offset)
+}
+
+/// Returns the naive datetime in the specified timezone or else in UTC.
+///
+/// An ambiguous local time, which occurs twice when the clock is set back, resolves to the earliest time.
+/// A non-existent local time, which is skipped when the clock is set forward,
+/// resolves to the same time after the gap (for example, 02:30 becomes 03:30).
+fn from_local_datetime(datetime: &NaiveDateTime, timezone: Option<Tz>) -> DateTime<FixedOffset> {
+    let Some(timezone) = timezone else {
+        return Utc.from_utc_datetime(datetime).fixed_offset();
+    };
+
+    match timezone.from_local_datetime(datetime) {
+        LocalResult::Single(datetime) => datetime.fixed_offset(),
+        LocalResult::Ambiguous(earliest, _) => earliest.fixed_offset(),
+        LocalResult::None => {
+            // Use the UTC offset from before the gap
+            let offset = timezone
+                .offset_from_utc_datetime(&(*datetime - TimeDelta::days(1)))
+                .fix();
+            DateTime::from_naive_utc_and_offset(*datetime - offset, offset)
+        }
+    }
+}
+
+/// Parses the programme start and stop time.
+///
+/// Without a stop time, the stop time is the start time plus the programme length,
+/// or else plus [`Options::default_duration`], or else the start time.
+pub(crate) fn parse_programme_times(
+    programme: &Programme,
+    options: &Options,
+) -> Result<(DateTime<FixedOffset>, DateTime<FixedOffset>), Error> {
+    let starttime_dt = parse_from_str(
+        &programme.start,
+        options.xmltv_datetime_format,
+        options.input_timezone,
+    )?;
+    let stoptime_dt = match &programme.stop {
+        Some(stoptime) => parse_from_str(
+            stoptime,
+            options.xmltv_datetime_format,
+            options.input_timezone,
+        )?,
+        None => {
+            let duration = match &programme.length {
+                Some(length) => length_to_time_delta(length),
+                None => options.default_duration.unwrap_or_default(),
+            };
+            starttime_dt.checked_add_signed(duration).ok_or_else(|| {
+                Error::InvalidDatetime(programme.start.clone(), "stop time out of range")
+            })?
+        }
+    };
+
+    Ok((starttime_dt, stoptime_dt))
+}
+
+/// Converts an XMLTV programme length.
+fn length_to_time_delta(length: &Length) -> TimeDelta {
+    let value = i64::from(length.length);
+    match length.units {
+        Units::Seconds => TimeDelta::seconds(value),
+        Units::Minutes => TimeDelta::minutes(value),
+        Units::Hours => TimeDelta::hours(value),
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Europe::Amsterdam;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_from_str() {
+        const TESTS: [(&str, Option<Tz>, &str); 7] = [
+            (
+                "20231029050000 +0200",
+                Some(Amsterdam),
+                "2023-10-29T03:00:00+00:00",
+            ),
+            ("20231029050000", None, "2023-10-29T05:00:00+00:00"),
+            // Summer time
+            (
+                "20230701120000",
+                Some(Amsterdam),
+                "2023-07-01T10:00:00+00:00",
+            ),
+            // Winter time
+            (
+                "20231201120000",
+                Some(Amsterdam),
+                "2023-12-01T11:00:00+00:00",
+            ),
+            // Ambiguous local time at the end of summer time resolves to the earliest time
+            (
+                "20231029023000",
+                Some(Amsterdam),
+                "2023-10-29T00:30:00+00:00",
+            ),
+            (
+                "20231029033000",
+                Some(Amsterdam),
+                "2023-10-29T02:30:00+00:00",
+            ),
+            // Non-existent local time at the start of summer time resolves to the time after the gap
+            (
+                "20240331023000",
+                Some(Amsterdam),
+                "2024-03-31T01:30:00+00:00",
+            ),
+        ];
+
+        for (datetime, timezone, expected) in TESTS {
+            let result = parse_from_str(datetime, DEFAULT_XMLTV_DATETIME_FORMAT, timezone).unwrap();
+            assert_eq!(
+                result.to_rfc3339(),
+                expected,
+                "for {datetime} in {timezone:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_xmltv_datetime() {
+        const TESTS: [(&str, &str); 15] = [
+            // Precision levels
+            ("2006", "2006-01-01T00:00:00+00:00"),
+            ("200607", "2006-07-01T00:00:00+00:00"),
+            ("20060715", "2006-07-15T00:00:00+00:00"),
+            ("2006071519", "2006-07-15T19:00:00+00:00"),
+            ("200607151930", "2006-07-15T19:30:00+00:00"),
+            ("20060715193045", "2006-07-15T19:30:45+00:00"),
+            // UTC offsets
+            ("20060715193045 +0100", "2006-07-15T19:30:45+01:00"),
+            ("20060715193045 -0330", "2006-07-15T19:30:45-03:30"),
+            ("20060715193045+01:00", "2006-07-15T19:30:45+01:00"),
+            ("200607151930 +01", "2006-07-15T19:30:00+01:00"),
+            ("200607 +0100", "2006-07-01T00:00:00+01:00"),
+            // Timezone abbreviations
+            ("200007281733 BST", "2000-07-28T17:33:00+01:00"),
+            ("20060715193045 utc", "2006-07-15T19:30:45+00:00"),
+            // IANA timezones
+            (
+                "20060715193045 Europe/Amsterdam",
+                "2006-07-15T19:30:45+02:00",
+            ),
+            (
+                "20060115193045 Europe/Amsterdam",
+                "2006-01-15T19:30:45+01:00",
+            ),
+        ];
+
+        for (datetime, expected) in TESTS {
+            let result = parse_xmltv_datetime(datetime, None).unwrap();
+            assert_eq!(result.to_rfc3339(), expected, "for {datetime}");
+        }
+
+        // Datetime without timezone in the specified timezone
+        let result = parse_xmltv_datetime("200607151930", Some(Amsterdam)).unwrap();
+        assert_eq!(result.to_rfc3339(), "2006-07-15T19:30:00+02:00");
+
+        const INVALID_TESTS: [&str; 8] = [
+            "",
+            "200",
+            "20060",
+            "200613",
+            "20060732",
+            "2006071525",
+            "20060715193045 +2",
+            "20060715193045 XYZ",
+        ];
+
+        for datetime in INVALID_TESTS {
+            let result = parse_xmltv_datetime(datetime, None);
+            assert!(
+                matches!(result, Err(Error::InvalidDatetime(..))),
+                "for {datetime:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_programme_times() {
+        let programme = Programme {
+            start: "20240101100000 +0000".to_string(),
+            ..Default::default()
+        };
+        let with_length = Programme {
+            length: Some(Length {
+                length: 2,
+                units: Units::Hours,
+            }),
+            ..programme.clone()
+        };
+        let with_stop = Programme {
+            stop: Some("20240101103000 +0000".to_string()),
+            ..with_length.clone()
+        };
+        let default_duration = Options {
+            default_duration: Some(TimeDelta::minutes(45)),
+            ..Default::default()
+        };
+
+        let tests = [
+            (&programme, &Options::default(), "2024-01-01T10:00:00+00:00"),
+            (&programme, &default_duration, "2024-01-01T10:45:00+00:00"),
+            (&with_length, &default_duration, "2024-01-01T12:00:00+00:00"),
+            (&with_stop, &default_duration, "2024-01-01T10:30:00+00:00"),
+        ];
+        for (programme, options, expected) in tests {
+            let (_, stoptime_dt) = parse_programme_times(programme, options).unwrap();
+            assert_eq!(stoptime_dt.to_rfc3339(), expected);
+        }
+
+        let too_long = Options {
+            default_duration: Some(TimeDelta::max_value()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            parse_programme_times(&programme, &too_long),
+            Err(Error::InvalidDatetime(..))
+        ));
+    }
+}
@@ -0,0 +1,394 @@
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{de, errors::IllFormedError, Writer};
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use xmltv::{Channel, Programme, Tv};
+
+use crate::error::{Error, ProgrammeContext};
+
+/// XMLTV element of a repeat, see [`write_previously_shown`].
+const PREVIOUSLY_SHOWN: &str = "previously-shown";
+
+/// Name of the `<previously-shown>` element that the xmltv crate deserializes.
+const XMLTV_CRATE_PREVIOUSLY_SHOWN: &str = "previously-show";
+
+/// XMLTV element, as read by [`Reader`].
+pub enum Element {
+    /// The `<tv>` element with its attributes only. Its channels and programmes are empty.
+    Tv(Tv),
+    Channel(Channel),
+    Programme(Box<Programme>),
+}
+
+/// Position in the (decompressed) XMLTV input.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Position {
+    /// Line number, starting at 1
+    pub line: u64,
+
+    /// Byte offset, starting at 0
+    pub offset: u64,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, byte offset {}", self.line, self.offset)
+    }
+}
+
+/// Event-driven XMLTV reader.
+///
+/// Reads one `<tv>`, `<channel>` or `<programme>` element at a time,
+/// so that the whole XMLTV TV listing does not have to be kept in memory.
+pub struct Reader<R: BufRead> {
+    reader: quick_xml::Reader<LineCounter<R>>,
+    buf: Vec<u8>,
+    depth: usize,
+
+    /// Position of the last element read
+    position: Position,
+
+    /// Number of programmes read
+    programmes: usize,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: quick_xml::Reader::from_reader(LineCounter::new(reader)),
+            buf: vec![],
+            depth: 0,
+            position: Position::default(),
+            programmes: 0,
+        }
+    }
+
+    /// Returns the position of the start of the last element read.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Returns the next XMLTV element or else `None` at the end of the input.
+    ///
+    /// Errors are returned with the position in the input
+    /// and, for a `<programme>` element, with the programme context.
+    pub fn read_element(&mut self) -> Result<Option<Element>, Error> {
+        loop {
+            self.buf.clear();
+            let offset = self.reader.buffer_position() as u64;
+            // Count the lines up to the event, so that the line counter only keeps the line feeds of one event
+            self.reader.get_mut().position(offset);
+            let event = self.reader.read_event_into(&mut self.buf);
+            let (start, is_empty) =
+                match event.map_err(|err| syntax_error(&mut self.reader, err))? {
+                    Event::Start(start) => (start.into_owned(), false),
+                    Event::Empty(start) => (start.into_owned(), true),
+                    Event::End(_) => {
+                        self.depth = self.depth.saturating_sub(1);
+                        continue;
+                    }
+                    Event::Eof => return Ok(None),
+                    _ => continue,
+                };
+
+            let element = match (self.depth, start.name().as_ref()) {
+                (0, b"tv") => {
+                    self.position = self.reader.get_mut().position(offset);
+                    if !is_empty {
+                        self.depth += 1;
+                    }
+                    // Deserialize the attributes only
+                    let tv = write_empty(start).and_then(|fragment| deserialize(&fragment));
+                    Element::Tv(tv.map_err(|err| self.error(err))?)
+                }
+                (1, b"channel") => {
+                    self.position = self.reader.get_mut().position(offset);
+                    let channel = self.read_fragment(start, is_empty);
+                    Element::Channel(channel.map_err(|err| self.error(err))?)
+                }
+                (1, b"programme") => {
+                    self.position = self.reader.get_mut().position(offset);
+                    self.programmes += 1;
+                    let context = self.programme_context(&start);
+                    let programme = self.read_fragment(start, is_empty);
+                    Element::Programme(Box::new(programme.map_err(|err| context.error(err))?))
+                }
+                _ => {
+                    if !is_empty {
+                        self.buf.clear();
+                        let result = self.reader.read_to_end_into(start.name(), &mut self.buf);
+                        result.map_err(|err| syntax_error(&mut self.reader, err))?;
+                    }
+                    continue;
+                }
+            };
+
+            return Ok(Some(element));
+        }
+    }
+
+    /// Reads the whole XMLTV TV listing.
+    pub fn read_listing(mut self) -> Result<Tv, Error> {
+        let mut listing = Tv::default();
+        while let Some(element) = self.read_element()? {
+            match element {
+                Element::Tv(tv) => {
+                    listing = Tv {
+                        channels: listing.channels,
+                        programmes: listing.programmes,
+                        ..tv
+                    }
+                }
+                Element::Channel(channel) => listing.channels.push(channel),
+                Element::Programme(programme) => listing.programmes.push(*programme),
+            }
+        }
+
+        Ok(listing)
+    }
+
+    /// Reads the rest of the element and deserializes it.
+    fn read_fragment<T: DeserializeOwned>(
+        &mut self,
+        start: BytesStart<'static>,
+        is_empty: bool,
+    ) -> Result<T, Error> {
+        if is_empty {
+            return deserialize(&write_empty(start)?);
+        }
+
+        let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+        let mut writer = Writer::new(Vec::new());
+        writer.write_event(Event::Start(start))?;
+
+        let mut depth = 1;
+        while depth > 0 {
+            self.buf.clear();
+            let event = match self.reader.read_event_into(&mut self.buf) {
+                Ok(event) => event,
+                Err(err) => return Err(syntax_error(&mut self.reader, err)),
+            };
+            if name == "channel" && depth == 1 {
+                if let Some((src, is_empty)) = icon_src(&event) {
+                    // The xmltv crate deserializes a channel icon as text
+                    writer.write_event(Event::Start(BytesStart::new("icon")))?;
+                    writer.write_event(Event::Text(BytesText::new(&src)))?;
+                    if is_empty {
+                        writer.write_event(Event::End(BytesEnd::new("icon")))?;
+                    } else {
+                        depth += 1;
+                    }
+                    continue;
+                }
+            }
+            if let Some(is_empty) = write_previously_shown(&mut writer, &event)? {
+                if !is_empty {
+                    depth += 1;
+                }
+                continue;
+            }
+            match event {
+                Event::Start(_) => depth += 1,
+                Event::End(_) => depth -= 1,
+                Event::Eof => {
+                    let error = IllFormedError::MissingEndTag(name);
+                    return Err(quick_xml::Error::from(error).into());
+                }
+                _ => {}
+            }
+            writer.write_event(rename_element(event))?;
+        }
+
+        deserialize(&writer.into_inner())
+    }
+
+    /// Returns the programme context of a `<programme>` element from its attributes.
+    fn programme_context(&self, start: &BytesStart) -> ProgrammeContext {
+        let attribute = |name| attribute_value(start, name);
+
+        ProgrammeContext {
+            index: self.programmes - 1,
+            channel: attribute("channel").unwrap_or_default(),
+            start: attribute("start").unwrap_or_default(),
+            stop: attribute("stop"),
+            position: Some(self.position),
+        }
+    }
+
+    /// Returns the error with the position of the last element read, unless it already has a position.
+    fn error(&self, err: Error) -> Error {
+        match err {
+            Error::Position { .. } => err,
+            _ => Error::Position {
+                position: self.position,
+                source: Box::new(err),
+            },
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Element, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_element().transpose()
+    }
+}
+
+/// Returns the syntax error with its position.
+fn syntax_error<R>(reader: &mut quick_xml::Reader<LineCounter<R>>, err: quick_xml::Error) -> Error {
+    let offset = reader.error_position() as u64;
+    Error::Position {
+        position: reader.get_mut().position(offset),
+        source: Box::new(err.into()),
+    }
+}
+
+/// Writes a `<previously-shown>` element as the element that the xmltv crate deserializes,
+/// with the `start` and `channel` attributes as child elements.
+///
+/// Returns whether the element is empty, or `None` if the event does not start a `<previously-shown>` element.
+fn write_previously_shown(
+    writer: &mut Writer<Vec<u8>>,
+    event: &Event,
+) -> Result<Option<bool>, Error> {
+    let (start, is_empty) = match event {
+        Event::Start(start) => (start, false),
+        Event::Empty(start) => (start, true),
+        _ => return Ok(None),
+    };
+    if start.name().as_ref() != PREVIOUSLY_SHOWN.as_bytes() {
+        return Ok(None);
+    }
+
+    writer.write_event(Event::Start(BytesStart::new(XMLTV_CRATE_PREVIOUSLY_SHOWN)))?;
+    for name in ["start", "channel"] {
+        if let Some(value) = attribute_value(start, name) {
+            writer
+                .create_element(name)
+                .write_text_content(BytesText::new(&value))?;
+        }
+    }
+    if is_empty {
+        writer.write_event(Event::End(BytesEnd::new(XMLTV_CRATE_PREVIOUSLY_SHOWN)))?;
+    }
+
+    Ok(Some(is_empty))
+}
+
+/// Renames the end of `<previously-shown>` to the element name that the xmltv crate deserializes.
+/// See [`write_previously_shown`].
+fn rename_element(event: Event) -> Event {
+    match event {
+        Event::End(end) if end.name().as_ref() == PREVIOUSLY_SHOWN.as_bytes() => {
+            Event::End(BytesEnd::new(XMLTV_CRATE_PREVIOUSLY_SHOWN))
+        }
+        event => event,
+    }
+}
+
+/// Returns the `src` attribute of an `<icon>` element, and whether the element is empty.
+fn icon_src(event: &Event) -> Option<(String, bool)> {
+    let (start, is_empty) = match event {
+        Event::Start(start) => (start, false),
+        Event::Empty(start) => (start, true),
+        _ => return None,
+    };
+    if start.name().as_ref() != b"icon" {
+        return None;
+    }
+
+    let src = attribute_value(start, "src").unwrap_or_default();
+    Some((src, is_empty))
+}
+
+/// Returns the unescaped value of the attribute.
+fn attribute_value(start: &BytesStart, name: &str) -> Option<String> {
+    start
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|attribute| attribute.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+/// Writes an element without content.
+fn write_empty(start: BytesStart) -> Result<Vec<u8>, Error> {
+    let mut writer = Writer::new(Vec::new());
+    writer.write_event(Event::Empty(start))?;
+    Ok(writer.into_inner())
+}
+
+fn deserialize<T: DeserializeOwned>(fragment: &[u8]) -> Result<T, Error> {
+    Ok(de::from_reader(fragment)?)
+}
+
+/// Reader that counts the lines of the consumed input.
+struct LineCounter<R> {
+    reader: R,
+
+    /// Number of bytes consumed
+    offset: u64,
+
+    /// Line number at the last position returned
+    line: u64,
+
+    /// Offsets of the line feeds after the last position returned
+    line_feeds: Vec<u64>,
+}
+
+impl<R> LineCounter<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            offset: 0,
+            line: 1,
+            line_feeds: vec![],
+        }
+    }
+
+    /// Returns the position at the offset.
+    ///
+    /// The position can not be before the last position returned.
+    /// Line feeds before the offset are forgotten.
+    fn position(&mut self, offset: u64) -> Position {
+        let line_feeds = self
+            .line_feeds
+            .partition_point(|&line_feed| line_feed < offset);
+        self.line += line_feeds as u64;
+        self.line_feeds.drain(..line_feeds);
+
+        Position {
+            line: self.line,
+            offset,
+        }
+    }
+}
+
+impl<R: BufRead> Read for LineCounter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for LineCounter<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Ok(buf) = self.reader.fill_buf() {
+            let line_feeds = buf[..amt.min(buf.len())]
+                .iter()
+                .enumerate()
+                .filter(|(_, &byte)| byte == b'\n')
+                .map(|(i, _)| self.offset + i as u64);
+            self.line_feeds.extend(line_feeds);
+        }
+        self.offset += amt as u64;
+        self.reader.consume(amt);
+    }
+}